@@ -0,0 +1,18 @@
+; Prints a greeting, then counts down from 5 to 1.
+        .ORIG x3000
+        LEA R0, GREETING
+        PUTS
+        LD R1, COUNT
+LOOP    LD R0, ZERO
+        ADD R0, R0, R1
+        OUT
+        LD R0, NEWLINE
+        OUT
+        ADD R1, R1, #-1
+        BRp LOOP
+        HALT
+GREETING .STRINGZ "Hello, LC-3!\n"
+COUNT   .FILL #5
+ZERO    .FILL x30
+NEWLINE .FILL x0A
+        .END
@@ -0,0 +1,478 @@
+//! Two-pass LC-3 assembler.
+//!
+//! Turns assembly source into the big-endian, origin-prefixed object format that
+//! the [`loader`](crate::loader) reads as [`Format::Obj`](crate::loader::Format::Obj). The first
+//! pass assigns an address to every label, the second encodes each statement.
+//!
+//! Supported syntax:
+//! * every mnemonic of the LC-3 ISA, including `RET`, `JSRR` and all `BR` variants
+//! * the trap aliases `GETC`, `OUT`, `PUTS`, `IN`, `PUTSP` and `HALT`
+//! * the directives `.ORIG`, `.END`, `.FILL`, `.BLKW` and `.STRINGZ`
+//! * decimal (`#10`, `10`), hex (`x3000`, `0x3000`) and binary (`b1010`) literals
+//! * labels, optionally followed by `:`, and `;` comments
+
+use std::fmt;
+
+use crate::symbols::{LineMap, SymbolTable};
+use crate::utils::parse_number;
+
+/// An assembly error and the 1-based source line it occurred on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// An assembled program: its load address and the words that follow it, plus the
+/// labels it defines and the source line of every statement that emitted words.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub origin: u16,
+    pub words: Vec<u16>,
+    pub symbols: SymbolTable,
+    pub lines: Vec<(u16, usize)>,
+}
+
+impl Program {
+    /// Encodes the program as an object file: the origin followed by every word,
+    /// all big-endian.
+    pub fn to_obj_bytes(&self) -> Vec<u8> {
+        std::iter::once(self.origin)
+            .chain(self.words.iter().copied())
+            .flat_map(u16::to_be_bytes)
+            .collect()
+    }
+
+    /// Builds the debug line map, attributing every line to `file`.
+    pub fn line_map(&self, file: &str) -> LineMap {
+        let mut map = LineMap::new();
+        for &(address, line) in &self.lines {
+            map.insert(address, file, line);
+        }
+        map
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Str(Vec<u8>),
+}
+
+/// A source line split into its parts. Mnemonics and directives are upper-cased.
+struct Statement {
+    line: usize,
+    label: Option<String>,
+    op: Option<String>,
+    operands: Vec<Token>,
+}
+
+const TRAP_ALIASES: [(&str, u16); 6] = [
+    ("GETC", 0x20),
+    ("OUT", 0x21),
+    ("PUTS", 0x22),
+    ("IN", 0x23),
+    ("PUTSP", 0x24),
+    ("HALT", 0x25),
+];
+
+const MNEMONICS: [&str; 16] = [
+    "ADD", "AND", "NOT", "JMP", "RET", "JSR", "JSRR", "LD", "LDI", "LDR", "LEA", "ST", "STI",
+    "STR", "TRAP", "RTI",
+];
+
+const DIRECTIVES: [&str; 5] = [".ORIG", ".END", ".FILL", ".BLKW", ".STRINGZ"];
+
+/// Assembles `source` into a program.
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+    let statements = source
+        .lines()
+        .enumerate()
+        .map(|(i, text)| parse_line(i + 1, text))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // First pass: find the origin and give every label an address
+    let mut origin = None;
+    let mut address: u32 = 0;
+    let mut symbols = SymbolTable::new();
+    let mut body = Vec::new();
+    for stmt in statements {
+        if stmt.label.is_none() && stmt.op.is_none() {
+            continue;
+        }
+        if origin.is_none() {
+            if stmt.op.as_deref() != Some(".ORIG") || stmt.label.is_some() {
+                return Err(error(stmt.line, "expected .ORIG before any other statement"));
+            }
+            expect_operands(&stmt, 1)?;
+            let value = number_operand(&stmt, 0)?;
+            let start = fill_value(value).ok_or_else(|| error(stmt.line, "origin out of range"))?;
+            origin = Some(start);
+            address = start as u32;
+            continue;
+        }
+        if let Some(label) = &stmt.label {
+            if address > 0xFFFF {
+                return Err(error(stmt.line, "program extends past xFFFF"));
+            }
+            if symbols.address_of(label).is_some() {
+                return Err(error(stmt.line, format!("duplicate label '{}'", label)));
+            }
+            symbols.insert(label, address as u16);
+        }
+        match stmt.op.as_deref() {
+            Some(".END") => break,
+            Some(".ORIG") => return Err(error(stmt.line, "only one .ORIG block is supported")),
+            _ => {}
+        }
+        address += statement_size(&stmt)?;
+        if address > 0x10000 {
+            return Err(error(stmt.line, "program extends past xFFFF"));
+        }
+        body.push(stmt);
+    }
+    let origin = origin.ok_or_else(|| error(1, "missing .ORIG"))?;
+
+    // Second pass: encode every statement
+    let mut words = Vec::new();
+    let mut lines = Vec::new();
+    for stmt in &body {
+        let start = words.len();
+        let pc = origin.wrapping_add(start as u16);
+        encode(stmt, pc, &symbols, &mut words)?;
+        if words.len() > start {
+            lines.push((pc, stmt.line));
+        }
+    }
+
+    Ok(Program { origin, words, symbols, lines })
+}
+
+fn error(line: usize, message: impl Into<String>) -> AsmError {
+    AsmError { line, message: message.into() }
+}
+
+fn is_op(word: &str) -> bool {
+    let upper = word.to_ascii_uppercase();
+    MNEMONICS.contains(&upper.as_str())
+        || DIRECTIVES.contains(&upper.as_str())
+        || TRAP_ALIASES.iter().any(|(name, _)| *name == upper)
+        || branch_condition(&upper).is_some()
+}
+
+/// Returns the n/z/p bits of a `BR` mnemonic. A bare `BR` branches always.
+fn branch_condition(mnemonic: &str) -> Option<u16> {
+    let flags = mnemonic.strip_prefix("BR")?;
+    if flags.is_empty() {
+        return Some(0b111);
+    }
+    let mut cond = 0;
+    let mut rest = flags;
+    for (flag, bit) in [("N", 0b100), ("Z", 0b010), ("P", 0b001)] {
+        if let Some(r) = rest.strip_prefix(flag) {
+            cond |= bit;
+            rest = r;
+        }
+    }
+    rest.is_empty().then_some(cond)
+}
+
+fn tokenize(line: usize, text: &str) -> Result<Vec<Token>, AsmError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            ';' => break,
+            ',' => {
+                chars.next();
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '"' => {
+                chars.next();
+                let mut bytes = Vec::new();
+                loop {
+                    match chars.next() {
+                        None => return Err(error(line, "unterminated string")),
+                        Some('"') => break,
+                        Some('\\') => {
+                            let escaped = match chars.next() {
+                                Some('n') => b'\n',
+                                Some('t') => b'\t',
+                                Some('r') => b'\r',
+                                Some('0') => 0,
+                                Some('\\') => b'\\',
+                                Some('"') => b'"',
+                                other => {
+                                    return Err(error(line, format!("invalid escape sequence '\\{}'", other.unwrap_or(' '))));
+                                }
+                            };
+                            bytes.push(escaped);
+                        }
+                        Some(c) if c.is_ascii() => bytes.push(c as u8),
+                        Some(c) => return Err(error(line, format!("non-ASCII character '{}' in string", c))),
+                    }
+                }
+                tokens.push(Token::Str(bytes));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == ',' || c == ';' || c == '"' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_line(line: usize, text: &str) -> Result<Statement, AsmError> {
+    let mut tokens = tokenize(line, text)?.into_iter().peekable();
+    let mut stmt = Statement { line, label: None, op: None, operands: Vec::new() };
+
+    if let Some(Token::Word(word)) = tokens.peek() {
+        if !is_op(word) {
+            let label = word.strip_suffix(':').unwrap_or(word);
+            if !is_valid_label(label) {
+                return Err(error(line, format!("invalid label '{}'", label)));
+            }
+            stmt.label = Some(label.to_string());
+            tokens.next();
+        }
+    }
+    match tokens.next() {
+        Some(Token::Word(word)) if is_op(&word) => stmt.op = Some(word.to_ascii_uppercase()),
+        Some(Token::Word(word)) => return Err(error(line, format!("unknown instruction '{}'", word))),
+        Some(Token::Str(_)) => return Err(error(line, "unexpected string")),
+        None => {}
+    }
+    stmt.operands = tokens.collect();
+    Ok(stmt)
+}
+
+fn is_valid_label(label: &str) -> bool {
+    let mut chars = label.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && parse_register(label).is_none()
+        && parse_number(label).is_none()
+}
+
+/// Number of words a statement occupies.
+fn statement_size(stmt: &Statement) -> Result<u32, AsmError> {
+    Ok(match stmt.op.as_deref() {
+        None => 0,
+        Some(".BLKW") => {
+            expect_operands(stmt, 1)?;
+            let count = number_operand(stmt, 0)?;
+            if !(0..=0xFFFF).contains(&count) {
+                return Err(error(stmt.line, ".BLKW count out of range"));
+            }
+            count as u32
+        }
+        Some(".STRINGZ") => {
+            expect_operands(stmt, 1)?;
+            match &stmt.operands[0] {
+                Token::Str(bytes) => bytes.len() as u32 + 1,
+                Token::Word(_) => return Err(error(stmt.line, ".STRINGZ expects a quoted string")),
+            }
+        }
+        Some(_) => 1,
+    })
+}
+
+fn expect_operands(stmt: &Statement, count: usize) -> Result<(), AsmError> {
+    if stmt.operands.len() != count {
+        let op = stmt.op.as_deref().unwrap_or_default();
+        return Err(error(stmt.line, format!("{} expects {} operand(s), found {}", op, count, stmt.operands.len())));
+    }
+    Ok(())
+}
+
+fn word_operand(stmt: &Statement, index: usize) -> Result<&str, AsmError> {
+    match &stmt.operands[index] {
+        Token::Word(word) => Ok(word),
+        Token::Str(_) => Err(error(stmt.line, "unexpected string operand")),
+    }
+}
+
+fn number_operand(stmt: &Statement, index: usize) -> Result<i32, AsmError> {
+    let word = word_operand(stmt, index)?;
+    parse_number(word).ok_or_else(|| error(stmt.line, format!("expected a number, found '{}'", word)))
+}
+
+fn parse_register(text: &str) -> Option<u16> {
+    let digit = text.strip_prefix('R').or_else(|| text.strip_prefix('r'))?;
+    match digit.as_bytes() {
+        [d @ b'0'..=b'7'] => Some((d - b'0') as u16),
+        _ => None,
+    }
+}
+
+fn register_operand(stmt: &Statement, index: usize) -> Result<u16, AsmError> {
+    let word = word_operand(stmt, index)?;
+    parse_register(word).ok_or_else(|| error(stmt.line, format!("expected a register, found '{}'", word)))
+}
+
+/// Converts a literal to a 16-bit word, accepting both signed and unsigned forms.
+fn fill_value(value: i32) -> Option<u16> {
+    (-0x8000..=0xFFFF).contains(&value).then_some(value as u16)
+}
+
+/// Checks that `value` fits in a `bits`-wide two's complement field and masks it.
+fn signed_field(stmt: &Statement, value: i32, bits: u32) -> Result<u16, AsmError> {
+    let min = -(1 << (bits - 1));
+    let max = (1 << (bits - 1)) - 1;
+    if value < min || value > max {
+        return Err(error(stmt.line, format!("value {} does not fit in {} bits", value, bits)));
+    }
+    Ok((value as u16) & ((1 << bits) - 1))
+}
+
+fn immediate_operand(stmt: &Statement, index: usize, bits: u32) -> Result<u16, AsmError> {
+    let value = number_operand(stmt, index)?;
+    signed_field(stmt, value, bits)
+}
+
+/// Resolves a label or a literal offset into a `bits`-wide PC-relative offset.
+/// `pc` is the address of the instruction being encoded.
+fn pc_offset_operand(
+    stmt: &Statement,
+    index: usize,
+    bits: u32,
+    pc: u16,
+    symbols: &SymbolTable,
+) -> Result<u16, AsmError> {
+    let word = word_operand(stmt, index)?;
+    let offset = match parse_number(word) {
+        Some(value) => value,
+        None => {
+            let target = symbols
+                .address_of(word)
+                .ok_or_else(|| error(stmt.line, format!("undefined label '{}'", word)))?;
+            target as i32 - (pc as i32 + 1)
+        }
+    };
+    signed_field(stmt, offset, bits)
+}
+
+fn encode(stmt: &Statement, pc: u16, symbols: &SymbolTable, words: &mut Vec<u16>) -> Result<(), AsmError> {
+    let Some(op) = stmt.op.as_deref() else {
+        return Ok(());
+    };
+
+    if let Some(cond) = branch_condition(op) {
+        expect_operands(stmt, 1)?;
+        words.push((cond << 9) | pc_offset_operand(stmt, 0, 9, pc, symbols)?);
+        return Ok(());
+    }
+    if let Some((_, vector)) = TRAP_ALIASES.iter().find(|(name, _)| *name == op) {
+        expect_operands(stmt, 0)?;
+        words.push(0xF000 | vector);
+        return Ok(());
+    }
+
+    let word = match op {
+        "ADD" | "AND" => {
+            expect_operands(stmt, 3)?;
+            let base = if op == "ADD" { 0x1000 } else { 0x5000 };
+            let dr = register_operand(stmt, 0)?;
+            let sr1 = register_operand(stmt, 1)?;
+            let last = match parse_register(word_operand(stmt, 2)?) {
+                Some(sr2) => sr2,
+                None => (1 << 5) | immediate_operand(stmt, 2, 5)?,
+            };
+            base | (dr << 9) | (sr1 << 6) | last
+        }
+        "NOT" => {
+            expect_operands(stmt, 2)?;
+            0x903F | (register_operand(stmt, 0)? << 9) | (register_operand(stmt, 1)? << 6)
+        }
+        "JMP" => {
+            expect_operands(stmt, 1)?;
+            0xC000 | (register_operand(stmt, 0)? << 6)
+        }
+        "RET" => {
+            expect_operands(stmt, 0)?;
+            0xC1C0
+        }
+        "JSR" => {
+            expect_operands(stmt, 1)?;
+            0x4800 | pc_offset_operand(stmt, 0, 11, pc, symbols)?
+        }
+        "JSRR" => {
+            expect_operands(stmt, 1)?;
+            0x4000 | (register_operand(stmt, 0)? << 6)
+        }
+        "LD" | "LDI" | "LEA" | "ST" | "STI" => {
+            expect_operands(stmt, 2)?;
+            let base = match op {
+                "LD" => 0x2000,
+                "LDI" => 0xA000,
+                "LEA" => 0xE000,
+                "ST" => 0x3000,
+                _ => 0xB000,
+            };
+            base | (register_operand(stmt, 0)? << 9) | pc_offset_operand(stmt, 1, 9, pc, symbols)?
+        }
+        "LDR" | "STR" => {
+            expect_operands(stmt, 3)?;
+            let base = if op == "LDR" { 0x6000 } else { 0x7000 };
+            base | (register_operand(stmt, 0)? << 9)
+                | (register_operand(stmt, 1)? << 6)
+                | immediate_operand(stmt, 2, 6)?
+        }
+        "TRAP" => {
+            expect_operands(stmt, 1)?;
+            let vector = number_operand(stmt, 0)?;
+            if !(0..=0xFF).contains(&vector) {
+                return Err(error(stmt.line, "trap vector must be between x00 and xFF"));
+            }
+            0xF000 | vector as u16
+        }
+        "RTI" => {
+            expect_operands(stmt, 0)?;
+            0x8000
+        }
+        ".FILL" => {
+            expect_operands(stmt, 1)?;
+            let operand = word_operand(stmt, 0)?;
+            match parse_number(operand) {
+                Some(value) => fill_value(value)
+                    .ok_or_else(|| error(stmt.line, format!("value {} does not fit in 16 bits", value)))?,
+                None => symbols
+                    .address_of(operand)
+                    .ok_or_else(|| error(stmt.line, format!("undefined label '{}'", operand)))?,
+            }
+        }
+        ".BLKW" => {
+            let count = number_operand(stmt, 0)? as usize;
+            words.extend(std::iter::repeat_n(0, count));
+            return Ok(());
+        }
+        ".STRINGZ" => {
+            if let Token::Str(bytes) = &stmt.operands[0] {
+                words.extend(bytes.iter().map(|&b| b as u16));
+            }
+            words.push(0);
+            return Ok(());
+        }
+        other => return Err(error(stmt.line, format!("unexpected '{}'", other))),
+    };
+    words.push(word);
+    Ok(())
+}
@@ -0,0 +1,269 @@
+//! Command-line interface of the `lc3_vm` binary.
+//!
+//! [`parse`] turns the arguments into a [`Command`]; [`RunOptions::load`] then
+//! builds the memory image the options describe, so the binary itself only has to
+//! set up consoles and report results.
+
+use std::time::Duration;
+
+use crate::loader::{Format, Image};
+use crate::snapshot::Snapshot;
+use crate::symbols::DebugInfo;
+use crate::utils::parse_number;
+use crate::vm::{StopReason, Vm};
+
+pub const USAGE: &str = "\
+Usage: lc3_vm [run] [options] <program.obj>...
+       lc3_vm [run] [options] --restore <snapshot> [<program.obj>...]
+       lc3_vm [run] --headless [--input <file|text>] [--output <file>] [options] <program.obj>...
+       lc3_vm asm <program.asm> [-o <program.obj>]
+       lc3_vm disasm <program.obj> [--sym <program.sym>]
+       lc3_vm convert <input> <output.obj|.hex|.bin|.ihex>
+       lc3_vm help";
+
+/// Printed after [`USAGE`] by `lc3_vm help`.
+pub const HELP: &str = "\
+Runs one or more programs loaded into a single memory image. Each file starts
+with its origin word; the files must not overlap. Execution starts at the origin
+of the first file unless --entry says otherwise.
+
+Programs may be .obj files, lc3tools text files with one word per line in hex
+(.hex) or binary (.bin), or Intel HEX (.ihex, .ihx); the format is taken from
+the extension, or recognised from the contents for any other name.
+convert rewrites a program in the format its output extension names.
+
+Options:
+  --entry <addr|label>   start at an address, or a label from the programs' .sym files
+  --os <os.obj>          boot an operating system image in x0000-x2FFF, which
+                         then handles TRAPs and starts the program itself
+  --restore <file>       start from a snapshot saved with the debugger's save
+                         command; programs given as well are loaded over it
+  --trace <file>         log every executed instruction, as JSON Lines if the
+                         file ends in .jsonl and as text otherwise
+  --profile <file>       count executions per address, opcode and subroutine and
+                         write a report to the file, and folded call stacks for
+                         flame graph tools to <file>.folded
+  --coverage <file>      record which instructions and branch outcomes ran and
+                         write lcov data if the file ends in .info or .lcov,
+                         and an annotated listing otherwise
+  --stop-on-exception    stop and report illegal opcodes and user-mode RTI
+                         instead of jumping to the OS exception handlers
+  --debug                run under the interactive debugger
+  --gdb <port>           serve the program to a GDB client on localhost:<port>
+  --max-instructions <n> stop after executing n instructions
+  --timeout <seconds>    stop once the program has run this long; fractions allowed
+  --headless             run without a terminal: input comes from --input, a
+                         file or else the text itself, and output goes to
+                         --output or stdout; without --os or an OS snapshot
+                         it stops on exceptions as --stop-on-exception does
+  -h, --help             show this help
+
+Exit status: 0 when the program halts, 1 if it cannot be loaded, 2 for bad
+arguments, 3 on an illegal opcode or privilege violation, 4 when it waits for
+input after the --headless input has run out, 5 when it reaches
+--max-instructions, and 6 when it runs past --timeout.";
+
+/// Exit status for a program that halted
+pub const EXIT_HALTED: i32 = 0;
+/// Exit status for a program stopped by an exception
+pub const EXIT_FAULT: i32 = 3;
+/// Exit status for a program that needed more input than it was given
+pub const EXIT_INPUT_EXHAUSTED: i32 = 4;
+/// Exit status for a program stopped by --max-instructions
+pub const EXIT_INSTRUCTION_LIMIT: i32 = 5;
+/// Exit status for a program stopped by --timeout
+pub const EXIT_TIMEOUT: i32 = 6;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Run(Box<RunOptions>),
+    Assemble { input: String, output: Option<String> },
+    Disassemble { input: String, symbols: Option<String> },
+    Convert { input: String, output: String },
+    Help,
+}
+
+/// How the loaded programs are driven.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Straight through on the terminal
+    #[default]
+    Run,
+    /// Under the interactive debugger
+    Debug,
+    /// Under a GDB client connecting to this port
+    Gdb(u16),
+    /// Without a terminal, on scripted input and captured output
+    Headless,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunOptions {
+    pub programs: Vec<String>,
+    /// Address or label to start at instead of the first program's origin
+    pub entry: Option<String>,
+    pub os: Option<String>,
+    /// Snapshot to restore before loading the programs
+    pub restore: Option<String>,
+    pub trace: Option<String>,
+    /// Where to write the profile report; folded stacks go next to it
+    pub profile: Option<String>,
+    pub coverage: Option<String>,
+    pub stop_on_exception: bool,
+    pub mode: Mode,
+    /// Headless input: a file name, or else the input itself
+    pub input: Option<String>,
+    /// Where headless output goes instead of stdout
+    pub output: Option<String>,
+    pub max_instructions: Option<u64>,
+    pub timeout: Option<Duration>,
+}
+
+/// Parses the arguments after the program name.
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    match args.first().map(String::as_str) {
+        Some("help" | "-h" | "--help") => Ok(Command::Help),
+        Some("asm") => match &args[1..] {
+            [input] => Ok(Command::Assemble { input: input.clone(), output: None }),
+            [input, flag, output] if flag == "-o" => {
+                Ok(Command::Assemble { input: input.clone(), output: Some(output.clone()) })
+            }
+            _ => Err("asm takes <program.asm> [-o <program.obj>]".to_string()),
+        },
+        Some("disasm") => match &args[1..] {
+            [input] => Ok(Command::Disassemble { input: input.clone(), symbols: None }),
+            [input, flag, symbols] if flag == "--sym" => {
+                Ok(Command::Disassemble { input: input.clone(), symbols: Some(symbols.clone()) })
+            }
+            _ => Err("disasm takes <program.obj> [--sym <program.sym>]".to_string()),
+        },
+        Some("convert") => match &args[1..] {
+            [input, output] => Ok(Command::Convert { input: input.clone(), output: output.clone() }),
+            _ => Err("convert takes <input> <output>".to_string()),
+        },
+        Some("run") => parse_run(&args[1..]),
+        _ => parse_run(args),
+    }
+}
+
+fn parse_run(args: &[String]) -> Result<Command, String> {
+    let mut options = RunOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--entry" => options.entry = Some(value()?),
+            "--os" => options.os = Some(value()?),
+            "--restore" => options.restore = Some(value()?),
+            "--trace" => options.trace = Some(value()?),
+            "--profile" => options.profile = Some(value()?),
+            "--coverage" => options.coverage = Some(value()?),
+            "--stop-on-exception" => options.stop_on_exception = true,
+            "--debug" | "--gdb" | "--headless" if options.mode != Mode::Run => {
+                return Err("only one of --debug, --gdb and --headless can be given".to_string());
+            }
+            "--debug" => options.mode = Mode::Debug,
+            "--headless" => options.mode = Mode::Headless,
+            "--input" => options.input = Some(value()?),
+            "--output" => options.output = Some(value()?),
+            "--max-instructions" => {
+                let n = value()?;
+                let n = n.parse().map_err(|_| format!("invalid instruction count '{}'", n))?;
+                options.max_instructions = Some(n);
+            }
+            "--timeout" => {
+                let seconds = value()?;
+                let timeout = seconds
+                    .parse()
+                    .ok()
+                    .and_then(|s: f64| Duration::try_from_secs_f64(s).ok())
+                    .ok_or_else(|| format!("invalid timeout '{}'", seconds))?;
+                options.timeout = Some(timeout);
+            }
+            "--gdb" => {
+                let port = value()?;
+                let port = port.parse().map_err(|_| format!("invalid port '{}'", port))?;
+                options.mode = Mode::Gdb(port);
+            }
+            flag if flag.starts_with('-') && flag.len() > 1 => return Err(format!("unknown option '{}'", flag)),
+            program => options.programs.push(program.to_string()),
+        }
+    }
+    if options.programs.is_empty() && options.restore.is_none() {
+        return Err("no program to run".to_string());
+    }
+    if options.entry.is_some() && options.os.is_some() {
+        return Err("--entry cannot be used with --os, which starts the program itself".to_string());
+    }
+    if (options.input.is_some() || options.output.is_some()) && options.mode != Mode::Headless {
+        return Err("--input and --output need --headless".to_string());
+    }
+    if options.restore.is_some() && options.os.is_some() {
+        return Err("--os cannot be used with --restore, whose snapshot holds the whole machine".to_string());
+    }
+    Ok(Command::Run(Box::new(options)))
+}
+
+impl RunOptions {
+    /// Restores the snapshot if there is one, loads the programs into `vm`, points
+    /// the PC at the entry point, and boots the OS image if there is one. Returns
+    /// the symbols and line maps found next to the programs.
+    pub fn load(&self, vm: &mut Vm) -> Result<DebugInfo, String> {
+        if let Some(path) = &self.restore {
+            vm.restore(&Snapshot::load(path).map_err(|e| e.to_string())?);
+        }
+        let mut image = Image::new();
+        let mut info = DebugInfo::new();
+        for program in &self.programs {
+            image.add_file(program, Format::Auto).map_err(|e| e.to_string())?;
+            // debug info is a convenience; a program without it still runs
+            let _ = info.merge_for(program);
+        }
+        vm.load_image(&image);
+
+        let entry = match &self.entry {
+            Some(text) => match parse_number(text) {
+                Some(value) if (0..=0xFFFF).contains(&value) => value as u16,
+                Some(_) => return Err(format!("entry point '{}' is not an address", text)),
+                None => info.symbols.address_of(text).ok_or_else(|| format!("unknown label '{}'", text))?,
+            },
+            None => match image.entry() {
+                Some(origin) => origin,
+                // a restored machine carries on from its own PC
+                None => return Ok(info),
+            },
+        };
+        vm.registers_mut().set_pc(entry);
+
+        if let Some(os) = &self.os {
+            vm.load_os(os).map_err(|e| e.to_string())?;
+        }
+        Ok(info)
+    }
+
+    /// The bytes to feed a headless run: the contents of the `--input` file if
+    /// there is one by that name, otherwise the text given.
+    pub fn input_bytes(&self) -> Result<Vec<u8>, String> {
+        match &self.input {
+            None => Ok(Vec::new()),
+            Some(input) if std::path::Path::new(input).is_file() => {
+                std::fs::read(input).map_err(|e| format!("{}: {}", input, e))
+            }
+            Some(text) => Ok(text.clone().into_bytes()),
+        }
+    }
+}
+
+/// The process exit status for a run that stopped for `reason`.
+pub fn exit_code(reason: StopReason) -> i32 {
+    match reason {
+        StopReason::Halted | StopReason::MachineHalted => EXIT_HALTED,
+        StopReason::IllegalOpcode { .. } | StopReason::PrivilegeViolation { .. } => EXIT_FAULT,
+        StopReason::IoWait => EXIT_INPUT_EXHAUSTED,
+        StopReason::InstructionLimit => EXIT_INSTRUCTION_LIMIT,
+        StopReason::Timeout => EXIT_TIMEOUT,
+        // runs from the command line have no breakpoints or watchpoints
+        StopReason::Breakpoint(_) | StopReason::Watchpoint { .. } => EXIT_HALTED,
+    }
+}
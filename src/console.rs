@@ -0,0 +1,228 @@
+//! Console I/O backends.
+//!
+//! Everything a program reads or writes — the GETC/OUT/PUTS/IN/PUTSP traps and the
+//! keyboard device registers — goes through a [`Console`], so the same program can
+//! run on a raw terminal, over pipes, or against in-memory buffers.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, stdin, stdout, Read, Stdout, Write};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use termion::raw::{IntoRawMode, RawTerminal};
+
+pub trait Console {
+    /// Blocks until a character is available. Returns `None` once input is closed.
+    fn read_char(&mut self) -> Option<u8>;
+
+    /// Returns the next character if one is already available, without blocking.
+    fn poll_char(&mut self) -> Option<u8>;
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()>;
+
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Writes a message from the VM itself rather than the program, such as the
+    /// notice that HALT ran. Consoles that capture program output can send these
+    /// elsewhere.
+    fn write_notice(&mut self, text: &str) -> io::Result<()> {
+        self.write_bytes(text.as_bytes())?;
+        self.flush()
+    }
+}
+
+/// Reads stdin on a background thread so that polling never blocks the VM.
+struct StdinReader {
+    chars: Receiver<u8>,
+}
+
+impl StdinReader {
+    fn spawn() -> Self {
+        let (tx, chars) = mpsc::channel();
+        thread::spawn(move || {
+            for byte in stdin().lock().bytes() {
+                match byte {
+                    Ok(b) if tx.send(b).is_ok() => {}
+                    _ => break,
+                }
+            }
+        });
+        Self { chars }
+    }
+
+    fn read_char(&mut self) -> Option<u8> {
+        self.chars.recv().ok()
+    }
+
+    fn poll_char(&mut self) -> Option<u8> {
+        self.chars.try_recv().ok()
+    }
+}
+
+/// Interactive terminal in raw mode: keys arrive unbuffered and without echo.
+///
+/// Raw mode also disables output post-processing, so `\n` is written as `\r\n`.
+pub struct TerminalConsole {
+    input: StdinReader,
+    output: RawTerminal<Stdout>,
+}
+
+impl TerminalConsole {
+    /// Switches the terminal to raw mode; it is restored when the console is dropped.
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            input: StdinReader::spawn(),
+            output: stdout().into_raw_mode()?,
+        })
+    }
+}
+
+impl Console for TerminalConsole {
+    fn read_char(&mut self) -> Option<u8> {
+        self.input.read_char()
+    }
+
+    fn poll_char(&mut self) -> Option<u8> {
+        self.input.poll_char()
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        for line in bytes.split_inclusive(|&b| b == b'\n') {
+            match line.strip_suffix(b"\n") {
+                Some(text) => {
+                    self.output.write_all(text)?;
+                    self.output.write_all(b"\r\n")?;
+                }
+                None => self.output.write_all(line)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Plain stdin/stdout with no terminal handling, for pipes and redirected files.
+pub struct PipeConsole {
+    input: StdinReader,
+    output: Stdout,
+}
+
+impl PipeConsole {
+    pub fn new() -> Self {
+        Self {
+            input: StdinReader::spawn(),
+            output: stdout(),
+        }
+    }
+}
+
+impl Default for PipeConsole {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Console for PipeConsole {
+    fn read_char(&mut self) -> Option<u8> {
+        self.input.read_char()
+    }
+
+    fn poll_char(&mut self) -> Option<u8> {
+        self.input.poll_char()
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.output.write_all(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+#[derive(Default)]
+struct Buffers {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+/// In-memory console for tests and embedders.
+///
+/// Clones share the same buffers, so a handle kept outside the VM can feed input
+/// and inspect output while the VM owns another.
+#[derive(Clone, Default)]
+pub struct BufferConsole {
+    buffers: Rc<RefCell<Buffers>>,
+}
+
+impl BufferConsole {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a console whose input is pre-loaded with `input`.
+    pub fn with_input(input: &[u8]) -> Self {
+        let console = Self::new();
+        console.push_input(input);
+        console
+    }
+
+    pub fn push_input(&self, input: &[u8]) {
+        self.buffers.borrow_mut().input.extend(input);
+    }
+
+    /// Returns a copy of everything written so far.
+    pub fn output(&self) -> Vec<u8> {
+        self.buffers.borrow().output.clone()
+    }
+
+    /// Returns everything written so far and clears the output buffer.
+    pub fn take_output(&self) -> Vec<u8> {
+        std::mem::take(&mut self.buffers.borrow_mut().output)
+    }
+}
+
+impl Console for BufferConsole {
+    fn read_char(&mut self) -> Option<u8> {
+        self.buffers.borrow_mut().input.pop_front()
+    }
+
+    fn poll_char(&mut self) -> Option<u8> {
+        self.buffers.borrow_mut().input.pop_front()
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.buffers.borrow_mut().output.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Console with no input that discards all output.
+pub struct NullConsole;
+
+impl Console for NullConsole {
+    fn read_char(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn poll_char(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn write_bytes(&mut self, _bytes: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,273 @@
+//! Code coverage.
+//!
+//! A [`Coverage`] attached to a [`Vm`](crate::Vm) counts how often each address
+//! executes and, for every BR, how often the branch was taken and not taken. It
+//! reports over the [`Segment`]s of the loaded programs, either as an annotated
+//! disassembly listing or as an lcov tracefile for `genhtml` and CI coverage
+//! tools. In lcov form, addresses map back to `.asm` lines through the programs'
+//! line maps; a program without one is reported by word offset, word 1 being
+//! the one at its origin.
+//!
+//! Coverage cannot tell code from data, so `.FILL`, `.BLKW` and `.STRINGZ` words
+//! show up as never executed.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::disasm::disassemble_with_symbols;
+use crate::loader::Segment;
+use crate::opcode::OpCode;
+use crate::symbols::{DebugInfo, SymbolTable};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoverageFormat {
+    /// Disassembly annotated with hit counts
+    Listing,
+    Lcov,
+}
+
+impl CoverageFormat {
+    /// `.info` and `.lcov` files get [`CoverageFormat::Lcov`], anything else a listing.
+    pub fn for_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("info" | "lcov") => Self::Lcov,
+            _ => Self::Listing,
+        }
+    }
+}
+
+/// How often a branch went each way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchCount {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    hits: BTreeMap<u16, u64>,
+    branches: BTreeMap<u16, BranchCount>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts the instruction `instr` at `pc`, executed with the condition flag
+    /// `cond` (4, 2 or 1 for N, Z or P) set.
+    pub fn record(&mut self, pc: u16, instr: u16, cond: u16) {
+        *self.hits.entry(pc).or_default() += 1;
+        if is_branch(instr) {
+            let branch = self.branches.entry(pc).or_default();
+            if (instr >> 9) & 0x7 & cond != 0 {
+                branch.taken += 1;
+            } else {
+                branch.not_taken += 1;
+            }
+        }
+    }
+
+    /// How often the instruction at `address` executed.
+    pub fn hits(&self, address: u16) -> u64 {
+        self.hits.get(&address).copied().unwrap_or(0)
+    }
+
+    /// The outcomes of the BR at `address`, if it executed.
+    pub fn branch(&self, address: u16) -> Option<BranchCount> {
+        self.branches.get(&address).copied()
+    }
+
+    /// Words of `segments` that executed and how many words there are, then branch
+    /// outcomes seen and how many there are.
+    fn totals(&self, segments: &[Segment]) -> (usize, usize, usize, usize) {
+        let (mut hit, mut words, mut outcomes, mut branches) = (0, 0, 0, 0);
+        for (address, word) in addresses(segments) {
+            words += 1;
+            hit += usize::from(self.hits(address) > 0);
+            if is_branch(word) {
+                branches += 2;
+                let count = self.branch(address).unwrap_or_default();
+                outcomes += usize::from(count.taken > 0) + usize::from(count.not_taken > 0);
+            }
+        }
+        (hit, words, outcomes, branches)
+    }
+
+    /// The disassembly of `segments`, each word prefixed with its hit count, or
+    /// `#####` if it never executed, and each BR followed by its outcomes.
+    pub fn listing(&self, segments: &[Segment], symbols: &SymbolTable) -> String {
+        let (hit, words, outcomes, branches) = self.totals(segments);
+        let mut out = format!(
+            "Words executed: {} of {} ({})\nBranch outcomes: {} of {} ({})\n",
+            hit,
+            words,
+            percent(hit, words),
+            outcomes,
+            branches,
+            percent(outcomes, branches)
+        );
+        for segment in segments {
+            let _ = writeln!(out, "\n{}", segment.name);
+            for (address, word) in addresses(std::slice::from_ref(segment)) {
+                let hits = match self.hits(address) {
+                    0 => "#####".to_string(),
+                    n => n.to_string(),
+                };
+                let _ = write!(
+                    out,
+                    "{:>9}  x{:04X}  {:04X}  {:<12} {}",
+                    hits,
+                    address,
+                    word,
+                    symbols.label_at(address).unwrap_or(""),
+                    disassemble_with_symbols(word, address, symbols)
+                );
+                if let Some(count) = self.branch(address) {
+                    let _ = write!(out, "  [taken {}, not taken {}]", count.taken, count.not_taken);
+                }
+                out.push('\n');
+            }
+        }
+        out
+    }
+
+    /// An lcov tracefile for `segments`, with `DA` line and `BRDA` branch records
+    /// per source file.
+    pub fn lcov(&self, segments: &[Segment], info: &DebugInfo) -> String {
+        // source file -> line -> address and word of the line's first word
+        let mut files: BTreeMap<String, BTreeMap<usize, (u16, u16)>> = BTreeMap::new();
+        for segment in segments {
+            for (i, (address, word)) in addresses(std::slice::from_ref(segment)).enumerate() {
+                let (file, line) = match info.lines.get(address) {
+                    Some(loc) => (loc.file.clone(), loc.line),
+                    None if info.lines.is_empty() => (segment.name.clone(), i + 1),
+                    // the rest of a multi-word line
+                    None => continue,
+                };
+                files.entry(file).or_default().entry(line).or_insert((address, word));
+            }
+        }
+
+        let mut out = String::from("TN:\n");
+        for (file, lines) in files {
+            let _ = writeln!(out, "SF:{}", file);
+            let (mut branches, mut branches_hit) = (0, 0);
+            for (&line, &(address, word)) in &lines {
+                if !is_branch(word) {
+                    continue;
+                }
+                // a branch that was never reached is listed with `-`
+                let outcomes = self.branch(address).map(|count| [count.taken, count.not_taken]);
+                for branch in 0..2 {
+                    let taken = outcomes.map_or("-".to_string(), |outcomes| outcomes[branch].to_string());
+                    let _ = writeln!(out, "BRDA:{},0,{},{}", line, branch, taken);
+                    branches_hit += usize::from(outcomes.is_some_and(|outcomes| outcomes[branch] > 0));
+                }
+                branches += 2;
+            }
+            for (&line, &(address, _)) in &lines {
+                let _ = writeln!(out, "DA:{},{}", line, self.hits(address));
+            }
+            let lines_hit = lines.values().filter(|&&(address, _)| self.hits(address) > 0).count();
+            let _ = write!(
+                out,
+                "BRF:{}\nBRH:{}\nLF:{}\nLH:{}\nend_of_record\n",
+                branches,
+                branches_hit,
+                lines.len(),
+                lines_hit
+            );
+        }
+        out
+    }
+}
+
+/// Whether `word` is a BR. With no condition bits it never branches, and
+/// disassembles as data: string characters, zero fills and `.BLKW` words.
+fn is_branch(word: u16) -> bool {
+    matches!(OpCode::from_instr(word), OpCode::BR) && (word >> 9) & 0x7 != 0
+}
+
+/// Every address of `segments` with the word loaded there.
+fn addresses(segments: &[Segment]) -> impl Iterator<Item = (u16, u16)> + '_ {
+    segments.iter().flat_map(|segment| {
+        segment.words.iter().enumerate().map(|(i, &word)| (segment.origin.wrapping_add(i as u16), word))
+    })
+}
+
+fn percent(part: usize, whole: usize) -> String {
+    match whole {
+        0 => "-".to_string(),
+        _ => format!("{:.1}%", part as f64 * 100.0 / whole as f64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols::LineMap;
+
+    fn program() -> (Vec<Segment>, Coverage) {
+        let segment = Segment {
+            name: "prog.obj".to_string(),
+            origin: 0x3000,
+            // BRz #1, ADD R0, R0, #1, HALT
+            words: vec![0x0401, 0x1021, 0xF025],
+        };
+        let mut coverage = Coverage::new();
+        coverage.record(0x3000, 0x0401, 2);
+        coverage.record(0x3002, 0xF025, 2);
+        (vec![segment], coverage)
+    }
+
+    #[test]
+    fn test_listing() {
+        let (segments, coverage) = program();
+        assert_eq!(coverage.branch(0x3000), Some(BranchCount { taken: 1, not_taken: 0 }));
+        let mut symbols = SymbolTable::new();
+        symbols.insert("DONE", 0x3002);
+        assert_eq!(
+            coverage.listing(&segments, &symbols),
+            concat!(
+                "Words executed: 2 of 3 (66.7%)\nBranch outcomes: 1 of 2 (50.0%)\n\nprog.obj\n",
+                "        1  x3000  0401               BRz DONE  [taken 1, not taken 0]\n",
+                "    #####  x3001  1021               ADD R0, R0, #1\n",
+                "        1  x3002  F025  DONE         HALT\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_lcov() {
+        let (segments, coverage) = program();
+        let mut info = DebugInfo::new();
+        assert_eq!(
+            coverage.lcov(&segments, &info),
+            "TN:\nSF:prog.obj\nBRDA:1,0,0,1\nBRDA:1,0,1,0\nDA:1,1\nDA:2,0\nDA:3,1\nBRF:2\nBRH:1\nLF:3\nLH:2\nend_of_record\n"
+        );
+
+        let mut lines = LineMap::new();
+        for (address, line) in [(0x3000, 4), (0x3001, 5), (0x3002, 7)] {
+            lines.insert(address, "prog.asm", line);
+        }
+        info.lines = lines;
+        assert!(coverage.lcov(&segments, &info).contains("SF:prog.asm\nBRDA:4,0,0,1\nBRDA:4,0,1,0\nDA:4,1\nDA:5,0\nDA:7,1\n"));
+    }
+
+    #[test]
+    fn test_string_data_and_unreached_branches() {
+        let (mut segments, coverage) = program();
+        // BRn #1 that never runs, then "Hi" as .STRINGZ
+        segments[0].words.extend([0x0801, 0x0048, 0x0069, 0x0000]);
+
+        let listing = coverage.listing(&segments, &SymbolTable::new());
+        assert!(listing.starts_with("Words executed: 2 of 7 (28.6%)\nBranch outcomes: 1 of 4 (25.0%)\n"));
+        assert!(listing.contains("    #####  x3003  0801               BRn x3005\n"));
+
+        let lcov = coverage.lcov(&segments, &DebugInfo::new());
+        assert!(lcov.contains("BRDA:1,0,0,1\nBRDA:1,0,1,0\nBRDA:4,0,0,-\nBRDA:4,0,1,-\nDA:1,1\n"));
+        assert!(lcov.contains("BRF:4\nBRH:1\nLF:7\nLH:2\n"));
+    }
+}
@@ -0,0 +1,544 @@
+//! Interactive debugger.
+//!
+//! [`Debugger`] wraps a [`Vm`] with a command prompt. Commands are read from and
+//! responses written to the VM's own console, so the debugger and the program being
+//! debugged share one terminal.
+//!
+//! The debugger keeps the VM's [history](crate::history) of the last
+//! [`HISTORY_LIMIT`] instructions, so execution can also be walked backwards.
+//!
+//! | command                       | effect                                         |
+//! |-------------------------------|------------------------------------------------|
+//! | `step [n]`, `s`               | execute `n` instructions, entering subroutines |
+//! | `next [n]`, `n`               | like `step`, but run JSR/JSRR/TRAP to return   |
+//! | `continue`, `c`               | run until a breakpoint or the program stops    |
+//! | `reverse-step [n]`, `rs`      | undo the last `n` instructions                 |
+//! | `reverse-continue`, `rc`      | undo back to the previous breakpoint           |
+//! | `lastwrite <addr\|label>`     | show the instruction that last stored there    |
+//! | `break <addr\|label>`, `b`    | set a breakpoint                               |
+//! | `delete [addr\|label]`, `d`   | remove one breakpoint, or all of them          |
+//! | `watch <addr\|label> [len]`   | stop after a write to the range                |
+//! | `rwatch`, `awatch`            | the same for reads, or for any access          |
+//! | `unwatch [addr\|label]`       | remove watchpoints covering an address, or all |
+//! | `regs`, `r`                   | show R0–R7, PC, PSR and saved stack pointers   |
+//! | `mem <addr> [len]`, `x`       | dump memory                                    |
+//! | `disas [addr] [count]`        | disassemble, starting at the PC by default     |
+//! | `set reg <Rn\|PC\|PSR> <val>` | change a register                              |
+//! | `set mem <addr> <value>`      | change a memory word                           |
+//! | `save <file>`, `load <file>`  | write or restore a machine [`Snapshot`]        |
+//! | `help`, `quit`                |                                                |
+
+use std::fmt::Write as _;
+
+use crate::disasm;
+use crate::memory::{WatchKind, Watchpoint};
+use crate::opcode::OpCode;
+use crate::register::Privilege;
+use crate::snapshot::Snapshot;
+use crate::symbols::DebugInfo;
+use crate::utils::parse_number;
+use crate::vm::{StopReason, Vm};
+
+const PROMPT: &str = "(lc3db) ";
+
+/// How many instructions the debugger can step back over
+pub const HISTORY_LIMIT: usize = 100_000;
+
+const HELP: &str = "\
+step [n]              execute n instructions (default 1)
+next [n]              step over JSR/JSRR/TRAP
+continue              run until a breakpoint or the program stops
+reverse-step [n]      undo the last n instructions (default 1)
+reverse-continue      undo back to the previous breakpoint
+lastwrite <addr>      show the instruction that last stored to an address
+break <addr|label>    set a breakpoint
+delete [addr|label]   delete a breakpoint, or all breakpoints
+watch <addr> [len]    stop after a write to the range
+rwatch <addr> [len]   stop after a read from the range
+awatch <addr> [len]   stop after any access to the range
+unwatch [addr|label]  delete watchpoints covering an address, or all of them
+regs                  show registers
+mem <addr> [len]      dump memory
+disas [addr] [count]  disassemble memory
+set reg <reg> <val>   change R0-R7, PC or PSR
+set mem <addr> <val>  change a memory word
+save <file>           save the machine state to a snapshot file
+load <file>           restore the machine state from a snapshot file
+quit                  leave the debugger
+";
+
+pub struct Debugger {
+    vm: Vm,
+    info: DebugInfo,
+    /// Why the program last stopped for good (halt or fault); it cannot resume after that
+    finished: Option<StopReason>,
+}
+
+impl Debugger {
+    pub fn new(mut vm: Vm, info: DebugInfo) -> Self {
+        vm.set_history_limit(HISTORY_LIMIT);
+        Self { vm, info, finished: None }
+    }
+
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut Vm {
+        &mut self.vm
+    }
+
+    /// Runs the command loop until `quit` or the console's input is closed.
+    pub fn repl(&mut self) {
+        let location = self.location(self.vm.registers().get_pc());
+        self.print(&location);
+        loop {
+            self.print(PROMPT);
+            let Some(line) = self.read_line() else {
+                break;
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if matches!(line, "quit" | "q" | "exit") {
+                break;
+            }
+            let output = self.execute(line);
+            self.print(&output);
+        }
+    }
+
+    /// Executes one command line and returns the text to show the user.
+    pub fn execute(&mut self, line: &str) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, args)) = words.split_first() else {
+            return String::new();
+        };
+        let result = match command {
+            "step" | "s" => self.step(args, false),
+            "next" | "n" => self.step(args, true),
+            "continue" | "c" => self.resume(|vm| Some(vm.run())),
+            "reverse-step" | "rs" => self.reverse_step(args),
+            "reverse-continue" | "rc" => self.reverse_continue(),
+            "lastwrite" => self.last_write(args),
+            "break" | "b" => self.add_breakpoint(args),
+            "delete" | "d" => self.delete_breakpoint(args),
+            "watch" => self.add_watchpoint(args, WatchKind::Write),
+            "rwatch" => self.add_watchpoint(args, WatchKind::Read),
+            "awatch" => self.add_watchpoint(args, WatchKind::Access),
+            "unwatch" => self.delete_watchpoint(args),
+            "regs" | "r" => Ok(self.registers()),
+            "mem" | "x" => self.dump_memory(args),
+            "disas" => self.disassemble(args),
+            "set" => self.set(args),
+            "save" => self.save(args),
+            "load" => self.load(args),
+            "help" | "h" | "?" => Ok(HELP.to_string()),
+            other => Err(format!("unknown command '{}', try 'help'", other)),
+        };
+        match result {
+            Ok(text) => text,
+            Err(message) => format!("error: {}\n", message),
+        }
+    }
+
+    fn read_line(&mut self) -> Option<String> {
+        let console = self.vm.console();
+        console.flush().ok()?;
+        let mut line = Vec::new();
+        loop {
+            match console.read_char() {
+                Some(b'\n') => break,
+                Some(c) => line.push(c),
+                None if line.is_empty() => return None,
+                None => break,
+            }
+        }
+        Some(String::from_utf8_lossy(&line).into_owned())
+    }
+
+    fn print(&mut self, text: &str) {
+        let console = self.vm.console();
+        let _ = console.write_bytes(text.as_bytes());
+        let _ = console.flush();
+    }
+
+    /// Parses a number or a label into an address.
+    fn address(&self, text: &str) -> Result<u16, String> {
+        match parse_number(text) {
+            Some(value) if (0..=0xFFFF).contains(&value) => return Ok(value as u16),
+            Some(_) => return Err(format!("'{}' is not an address", text)),
+            None => {}
+        }
+        self.info
+            .symbols
+            .address_of(text)
+            .ok_or_else(|| format!("unknown label '{}'", text))
+    }
+
+    /// Parses a 16-bit value, signed or unsigned, or a label's address.
+    fn value(&self, text: &str) -> Result<u16, String> {
+        match parse_number(text) {
+            Some(v) if (-0x8000..=0xFFFF).contains(&v) => Ok(v as u16),
+            Some(_) => Err(format!("value '{}' does not fit in 16 bits", text)),
+            None => self.address(text),
+        }
+    }
+
+    fn count(args: &[&str], index: usize, default: usize) -> Result<usize, String> {
+        match args.get(index) {
+            None => Ok(default),
+            Some(text) => parse_number(text)
+                .filter(|&n| n > 0)
+                .map(|n| n as usize)
+                .ok_or_else(|| format!("invalid count '{}'", text)),
+        }
+    }
+
+    /// `LABEL+n (file:line): xADDR  WORD  MNEMONIC` for the instruction at `address`.
+    fn location(&self, address: u16) -> String {
+        let word = self.vm.memory().peek(address);
+        format!(
+            "{}: x{:04X}  {:04X}  {}\n",
+            self.info.describe(address),
+            address,
+            word,
+            disasm::disassemble_with_symbols(word, address, &self.info.symbols)
+        )
+    }
+
+    /// Runs `f`, then reports where the program stopped and why, if it stopped
+    /// for any reason other than finishing the requested steps.
+    fn resume(&mut self, f: impl FnOnce(&mut Vm) -> Option<StopReason>) -> Result<String, String> {
+        if let Some(reason) = self.finished {
+            return Err(format!("the program is no longer running ({})", describe_stop(reason)));
+        }
+        let mut out = String::new();
+        match f(&mut self.vm) {
+            None => {}
+            Some(StopReason::Breakpoint(addr)) => {
+                let _ = writeln!(out, "Breakpoint at {}", self.info.describe(addr));
+            }
+            Some(
+                reason @ (StopReason::Halted
+                | StopReason::MachineHalted
+                | StopReason::IllegalOpcode { .. }
+                | StopReason::PrivilegeViolation { .. }),
+            ) => {
+                self.finished = Some(reason);
+                let _ = writeln!(out, "Program stopped: {}", describe_stop(reason));
+                return Ok(out);
+            }
+            Some(StopReason::IoWait) => {
+                let _ = writeln!(out, "Program is waiting for input");
+            }
+            Some(reason @ (StopReason::InstructionLimit | StopReason::Timeout)) => {
+                let _ = writeln!(out, "Stopped: {}", describe_stop(reason));
+            }
+            Some(StopReason::Watchpoint { pc, .. }) => {
+                let word = self.vm.memory().peek(pc);
+                let instr = disasm::disassemble_with_symbols(word, pc, &self.info.symbols);
+                for hit in self.vm.memory().watch_hits() {
+                    let _ = write!(out, "Watchpoint hit by {} ({}): ", instr, self.info.describe(pc));
+                    let _ = match hit.kind {
+                        WatchKind::Write => writeln!(
+                            out,
+                            "wrote {} x{:04X} -> x{:04X}",
+                            self.info.describe(hit.address),
+                            hit.old,
+                            hit.new
+                        ),
+                        _ => writeln!(out, "read {} x{:04X}", self.info.describe(hit.address), hit.new),
+                    };
+                }
+            }
+        }
+        out.push_str(&self.location(self.vm.registers().get_pc()));
+        Ok(out)
+    }
+
+    fn step(&mut self, args: &[&str], over: bool) -> Result<String, String> {
+        let count = Self::count(args, 0, 1)?;
+        self.resume(|vm| {
+            for _ in 0..count {
+                let reason = if over { step_over(vm) } else { vm.step() };
+                if reason.is_some() {
+                    return reason;
+                }
+            }
+            None
+        })
+    }
+
+    fn reverse_step(&mut self, args: &[&str]) -> Result<String, String> {
+        let count = Self::count(args, 0, 1)?;
+        let undone = (0..count).take_while(|_| self.vm.step_back().is_some()).count();
+        self.reversed(undone, "")
+    }
+
+    fn reverse_continue(&mut self) -> Result<String, String> {
+        let mut undone = 0;
+        while let Some(pc) = self.vm.step_back() {
+            undone += 1;
+            if self.vm.breakpoints().any(|addr| addr == pc) {
+                let message = format!("Breakpoint at {}\n", self.info.describe(pc));
+                return self.reversed(undone, &message);
+            }
+        }
+        self.reversed(undone, "")
+    }
+
+    /// Reports where stepping back over `undone` instructions left the program.
+    fn reversed(&mut self, undone: usize, message: &str) -> Result<String, String> {
+        if undone == 0 {
+            return Err("no recorded history to step back over".to_string());
+        }
+        // a halted program runs again once its last instruction is undone
+        self.finished = None;
+        let mut out = message.to_string();
+        if message.is_empty() && self.vm.history().is_empty() {
+            out.push_str("Reached the start of the recorded history\n");
+        }
+        out.push_str(&self.location(self.vm.registers().get_pc()));
+        Ok(out)
+    }
+
+    fn last_write(&self, args: &[&str]) -> Result<String, String> {
+        let [target] = args else {
+            return Err("usage: lastwrite <addr|label>".to_string());
+        };
+        let address = self.address(target)?;
+        let Some((undo, write)) = self.vm.history().last_write_to(address) else {
+            return Ok(format!("No recorded write to {}\n", self.info.describe(address)));
+        };
+        let instr = disasm::disassemble_with_symbols(undo.instr, undo.pc, &self.info.symbols);
+        Ok(format!(
+            "{} last written by {} ({}): x{:04X} -> x{:04X}\n",
+            self.info.describe(address),
+            instr,
+            self.info.describe(undo.pc),
+            write.old,
+            write.new
+        ))
+    }
+
+    fn add_breakpoint(&mut self, args: &[&str]) -> Result<String, String> {
+        let [target] = args else {
+            return Err("usage: break <addr|label>".to_string());
+        };
+        let address = self.address(target)?;
+        if self.vm.add_breakpoint(address) {
+            Ok(format!("Breakpoint set at {}\n", self.info.describe(address)))
+        } else {
+            Ok(format!("Breakpoint already set at {}\n", self.info.describe(address)))
+        }
+    }
+
+    fn delete_breakpoint(&mut self, args: &[&str]) -> Result<String, String> {
+        match args {
+            [] => {
+                let all: Vec<u16> = self.vm.breakpoints().collect();
+                for address in &all {
+                    self.vm.remove_breakpoint(*address);
+                }
+                Ok(format!("Deleted {} breakpoint(s)\n", all.len()))
+            }
+            [target] => {
+                let address = self.address(target)?;
+                if self.vm.remove_breakpoint(address) {
+                    Ok(format!("Deleted breakpoint at {}\n", self.info.describe(address)))
+                } else {
+                    Err(format!("no breakpoint at {}", self.info.describe(address)))
+                }
+            }
+            _ => Err("usage: delete [addr|label]".to_string()),
+        }
+    }
+
+    fn add_watchpoint(&mut self, args: &[&str], kind: WatchKind) -> Result<String, String> {
+        let (start, len) = match args {
+            [start] => (self.address(start)?, 1),
+            [start, _] => (self.address(start)?, Self::count(args, 1, 1)?),
+            _ => return Err("usage: watch <addr|label> [len]".to_string()),
+        };
+        let end = start
+            .checked_add((len - 1) as u16)
+            .filter(|_| len <= 0x10000)
+            .ok_or("watched range extends past xFFFF")?;
+        self.vm.add_watchpoint(Watchpoint { start, end, kind });
+        let range = if start == end {
+            self.info.describe(start)
+        } else {
+            format!("{}..{}", self.info.describe(start), self.info.describe(end))
+        };
+        let kind = match kind {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Access => "access",
+        };
+        Ok(format!("Watchpoint ({}) set on {}\n", kind, range))
+    }
+
+    fn delete_watchpoint(&mut self, args: &[&str]) -> Result<String, String> {
+        match args {
+            [] => {
+                let count = self.vm.memory().watchpoints().len();
+                self.vm.memory_mut().clear_watchpoints();
+                Ok(format!("Deleted {} watchpoint(s)\n", count))
+            }
+            [target] => {
+                let address = self.address(target)?;
+                match self.vm.remove_watchpoints_at(address) {
+                    0 => Err(format!("no watchpoint covers {}", self.info.describe(address))),
+                    count => Ok(format!("Deleted {} watchpoint(s)\n", count)),
+                }
+            }
+            _ => Err("usage: unwatch [addr|label]".to_string()),
+        }
+    }
+
+    fn registers(&self) -> String {
+        let regs = self.vm.registers();
+        let mut out = String::new();
+        for r in 0..8 {
+            let _ = write!(out, "R{} x{:04X}{}", r, regs.get(r), if r % 4 == 3 { "\n" } else { "  " });
+        }
+        let cond = match regs.get_cond_flag() {
+            4 => 'N',
+            2 => 'Z',
+            _ => 'P',
+        };
+        let mode = match regs.get_privilege() {
+            Privilege::Supervisor => "supervisor",
+            Privilege::User => "user",
+        };
+        let _ = writeln!(
+            out,
+            "PC x{:04X}  PSR x{:04X}  CC {}  {} mode, priority {}",
+            regs.get_pc(),
+            regs.get_psr(),
+            cond,
+            mode,
+            regs.get_priority()
+        );
+        let _ = writeln!(out, "Saved_SSP x{:04X}  Saved_USP x{:04X}", regs.get_saved_ssp(), regs.get_saved_usp());
+        out
+    }
+
+    fn dump_memory(&self, args: &[&str]) -> Result<String, String> {
+        let (start, len) = match args {
+            [start] => (self.address(start)?, 8),
+            [start, _] => (self.address(start)?, Self::count(args, 1, 8)?.min(0x10000)),
+            _ => return Err("usage: mem <addr> [len]".to_string()),
+        };
+        let mut out = String::new();
+        for row in 0..len.div_ceil(8) {
+            let row_start = start.wrapping_add((row * 8) as u16);
+            let _ = write!(out, "x{:04X}:", row_start);
+            for i in 0..(len - row * 8).min(8) {
+                let _ = write!(out, " {:04X}", self.vm.memory().peek(row_start.wrapping_add(i as u16)));
+            }
+            out.push('\n');
+        }
+        Ok(out)
+    }
+
+    fn disassemble(&self, args: &[&str]) -> Result<String, String> {
+        let start = match args.first() {
+            Some(text) => self.address(text)?,
+            None => self.vm.registers().get_pc(),
+        };
+        let count = Self::count(args, 1, 10)?;
+        let words: Vec<u16> = (0..count)
+            .map(|i| self.vm.memory().peek(start.wrapping_add(i as u16)))
+            .collect();
+        Ok(disasm::listing(start, &words, &self.info.symbols))
+    }
+
+    fn set(&mut self, args: &[&str]) -> Result<String, String> {
+        match args {
+            ["reg", reg, value] => {
+                let value = self.value(value)?;
+                let regs = self.vm.registers_mut();
+                match reg.to_ascii_uppercase().as_str() {
+                    "PC" => regs.set_pc(value),
+                    "PSR" => regs.set_psr(value),
+                    name => {
+                        let r = name
+                            .strip_prefix('R')
+                            .and_then(|d| d.parse::<usize>().ok())
+                            .filter(|&r| r < 8)
+                            .ok_or_else(|| format!("unknown register '{}'", reg))?;
+                        regs.set(r, value);
+                    }
+                }
+                Ok(String::new())
+            }
+            ["mem", address, value] => {
+                let address = self.address(address)?;
+                let value = self.value(value)?;
+                self.vm.memory_mut().write(address, value);
+                Ok(String::new())
+            }
+            _ => Err("usage: set reg <Rn|PC|PSR> <value> | set mem <addr> <value>".to_string()),
+        }
+    }
+
+    fn save(&mut self, args: &[&str]) -> Result<String, String> {
+        let [path] = args else {
+            return Err("usage: save <file>".to_string());
+        };
+        self.vm.snapshot().save(path).map_err(|e| e.to_string())?;
+        Ok(format!("Saved machine state to {}\n", path))
+    }
+
+    fn load(&mut self, args: &[&str]) -> Result<String, String> {
+        let [path] = args else {
+            return Err("usage: load <file>".to_string());
+        };
+        let snapshot = Snapshot::load(path).map_err(|e| e.to_string())?;
+        self.vm.restore(&snapshot);
+        // the restored program may still be running
+        self.finished = None;
+        Ok(format!("Restored machine state from {}\n{}", path, self.location(snapshot.pc)))
+    }
+}
+
+/// Executes one instruction, running a JSR/JSRR/TRAP through to the instruction
+/// after it. Breakpoints inside the called routine still stop execution.
+fn step_over(vm: &mut Vm) -> Option<StopReason> {
+    let pc = vm.registers().get_pc();
+    let instr = vm.memory().peek(pc);
+    if !matches!(OpCode::from_instr(instr), OpCode::JSR | OpCode::TRAP) {
+        return vm.step();
+    }
+    let return_address = pc.wrapping_add(1);
+    let temporary = vm.add_breakpoint(return_address);
+    let reason = vm.run();
+    if temporary {
+        vm.remove_breakpoint(return_address);
+    }
+    match reason {
+        StopReason::Breakpoint(addr) if addr == return_address => None,
+        other => Some(other),
+    }
+}
+
+fn describe_stop(reason: StopReason) -> String {
+    match reason {
+        StopReason::Halted => "halted".to_string(),
+        StopReason::MachineHalted => "machine halted via MCR".to_string(),
+        StopReason::Breakpoint(addr) => format!("breakpoint at x{:04X}", addr),
+        StopReason::IllegalOpcode { pc, instr } => {
+            format!("{:?} not implemented at x{:04X}", OpCode::from_instr(instr), pc)
+        }
+        StopReason::PrivilegeViolation { pc, .. } => format!("RTI in user mode at x{:04X}", pc),
+        StopReason::IoWait => "waiting for input".to_string(),
+        StopReason::InstructionLimit => "instruction limit reached".to_string(),
+        StopReason::Timeout => "timed out".to_string(),
+        StopReason::Watchpoint { pc, hit } => {
+            format!("watchpoint on x{:04X} hit at x{:04X}", hit.address, pc)
+        }
+    }
+}
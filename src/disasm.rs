@@ -0,0 +1,141 @@
+//! LC-3 disassembler.
+//!
+//! Turns any 16-bit word into the assembly text [`assembler`](crate::assembler)
+//! accepts. Words that do not decode to a well-formed instruction — reserved opcodes,
+//! non-zero reserved bits, a `BR` with no condition — are shown as `.FILL` data.
+
+use std::fmt::Write as _;
+
+use crate::opcode::OpCode;
+use crate::symbols::SymbolTable;
+use crate::utils::{get_bits, sign_extend};
+
+/// Disassembles the word stored at `address`. PC-relative targets are shown as
+/// absolute hex addresses.
+pub fn disassemble(instr: u16, address: u16) -> String {
+    disassemble_with_symbols(instr, address, &SymbolTable::new())
+}
+
+/// Disassembles the word stored at `address`, naming PC-relative targets after the
+/// labels in `symbols` where one matches exactly.
+pub fn disassemble_with_symbols(instr: u16, address: u16, symbols: &SymbolTable) -> String {
+    let target = |bits: usize| {
+        let offset = sign_extend(instr & ((1 << bits) - 1), bits);
+        let addr = address.wrapping_add(1).wrapping_add(offset);
+        match symbols.label_at(addr) {
+            Some(label) => label.to_string(),
+            None => format!("x{:04X}", addr),
+        }
+    };
+    let dr = get_bits(instr, 9, 3);
+    let sr1 = get_bits(instr, 6, 3);
+    let imm = |bits: usize| sign_extend(instr & ((1 << bits) - 1), bits) as i16;
+
+    match OpCode::from_instr(instr) {
+        OpCode::ADD | OpCode::AND => {
+            let name = if instr >> 12 == 1 { "ADD" } else { "AND" };
+            if get_bits(instr, 5, 1) == 1 {
+                format!("{} R{}, R{}, #{}", name, dr, sr1, imm(5))
+            } else if get_bits(instr, 3, 2) == 0 {
+                format!("{} R{}, R{}, R{}", name, dr, sr1, get_bits(instr, 0, 3))
+            } else {
+                fill(instr)
+            }
+        }
+        OpCode::NOT if get_bits(instr, 0, 6) == 0x3F => format!("NOT R{}, R{}", dr, sr1),
+        OpCode::BR if dr != 0 => {
+            let cond: String = [('n', 4), ('z', 2), ('p', 1)]
+                .iter()
+                .filter(|(_, bit)| dr & bit != 0)
+                .map(|(flag, _)| flag)
+                .collect();
+            format!("BR{} {}", cond, target(9))
+        }
+        OpCode::JMP if get_bits(instr, 9, 3) == 0 && get_bits(instr, 0, 6) == 0 => {
+            if sr1 == 7 {
+                "RET".to_string()
+            } else {
+                format!("JMP R{}", sr1)
+            }
+        }
+        OpCode::JSR if get_bits(instr, 11, 1) == 1 => format!("JSR {}", target(11)),
+        OpCode::JSR if get_bits(instr, 9, 2) == 0 && get_bits(instr, 0, 6) == 0 => {
+            format!("JSRR R{}", sr1)
+        }
+        OpCode::LD => format!("LD R{}, {}", dr, target(9)),
+        OpCode::LDI => format!("LDI R{}, {}", dr, target(9)),
+        OpCode::LEA => format!("LEA R{}, {}", dr, target(9)),
+        OpCode::ST => format!("ST R{}, {}", dr, target(9)),
+        OpCode::STI => format!("STI R{}, {}", dr, target(9)),
+        OpCode::LDR => format!("LDR R{}, R{}, #{}", dr, sr1, imm(6)),
+        OpCode::STR => format!("STR R{}, R{}, #{}", dr, sr1, imm(6)),
+        OpCode::RTI if get_bits(instr, 0, 12) == 0 => "RTI".to_string(),
+        OpCode::TRAP if get_bits(instr, 8, 4) == 0 => match instr & 0xFF {
+            0x20 => "GETC".to_string(),
+            0x21 => "OUT".to_string(),
+            0x22 => "PUTS".to_string(),
+            0x23 => "IN".to_string(),
+            0x24 => "PUTSP".to_string(),
+            0x25 => "HALT".to_string(),
+            vector => format!("TRAP x{:02X}", vector),
+        },
+        _ => fill(instr),
+    }
+}
+
+fn fill(word: u16) -> String {
+    format!(".FILL x{:04X}", word)
+}
+
+/// Formats a listing of `words` starting at `start`: one line per word with its
+/// address, the raw word, any label defined there, and the disassembly.
+pub fn listing(start: u16, words: &[u16], symbols: &SymbolTable) -> String {
+    let mut out = String::new();
+    for (i, &word) in words.iter().enumerate() {
+        let address = start.wrapping_add(i as u16);
+        let label = symbols.label_at(address).unwrap_or("");
+        let _ = writeln!(
+            out,
+            "x{:04X}  {:04X}  {:<12} {}",
+            address,
+            word,
+            label,
+            disassemble_with_symbols(word, address, symbols)
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        assert_eq!(disassemble(0x1283, 0x3000), "ADD R1, R2, R3");
+        assert_eq!(disassemble(0x12BF, 0x3000), "ADD R1, R2, #-1");
+        assert_eq!(disassemble(0x0BFB, 0x3004), "BRnp x3000");
+        assert_eq!(disassemble(0x0FFF, 0x3000), "BRnzp x3000");
+        assert_eq!(disassemble(0xC1C0, 0x3000), "RET");
+        assert_eq!(disassemble(0x40C0, 0x3000), "JSRR R3");
+        assert_eq!(disassemble(0x62A0, 0x3000), "LDR R1, R2, #-32");
+        assert_eq!(disassemble(0xF025, 0x3000), "HALT");
+        assert_eq!(disassemble(0xF030, 0x3000), "TRAP x30");
+    }
+
+    #[test]
+    fn test_data_words() {
+        assert_eq!(disassemble(0x0000, 0x3000), ".FILL x0000");
+        assert_eq!(disassemble(0xD000, 0x3000), ".FILL xD000");
+        assert_eq!(disassemble(0x1288, 0x3000), ".FILL x1288"); // reserved bits set
+        assert_eq!(disassemble(0x9000, 0x3000), ".FILL x9000"); // NOT without 111111
+    }
+
+    #[test]
+    fn test_symbols() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert("LOOP", 0x3000);
+        assert_eq!(disassemble_with_symbols(0x0BFF, 0x3000, &symbols), "BRnp LOOP");
+        assert_eq!(listing(0x3000, &[0x0BFF], &symbols), "x3000  0BFF  LOOP         BRnp LOOP\n");
+    }
+}
@@ -0,0 +1,419 @@
+//! GDB Remote Serial Protocol stub.
+//!
+//! [`GdbServer`] listens on a localhost TCP port and lets one RSP client (gdb, an IDE
+//! front-end, or a script) drive a [`Vm`]. The supported subset is register and
+//! memory access, single-stepping, continuing (interruptible with Ctrl-C), software
+//! and hardware breakpoints, and write/read/access watchpoints.
+//!
+//! Since there is no LC-3 architecture in gdb, the layout is defined here:
+//! * registers are numbered R0–R7 = 0–7, PC = 8, PSR = 9
+//! * memory is word-addressed: `m3000,2` reads the two words at x3000 and x3001
+//! * every register and memory word is sent as 4 hex digits, most significant byte
+//!   first, matching the byte order of `.obj` files
+//! * the length of a watchpoint (`Z2`–`Z4`) is a number of words
+//!
+//! The register layout is also served as a target description
+//! (`qXfer:features:read:target.xml`), so gdb does not guess it from the host.
+
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+
+use crate::memory::{WatchKind, Watchpoint, MR_PSR};
+use crate::vm::{StopReason, Vm};
+
+/// Instructions executed between checks for a Ctrl-C from the client
+const INTERRUPT_POLL_INTERVAL: usize = 10_000;
+
+/// Most words an `m` or `M` packet may transfer: as hex they fill the 0x4000-byte
+/// `PacketSize` sent in reply to `qSupported`
+const MAX_MEMORY_WORDS: usize = 0x4000 / 4;
+
+const NUM_REGISTERS: usize = 10;
+
+/// Target description matching the `g` packet layout
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.lc3.core">
+    <reg name="r0" bitsize="16" type="int16" regnum="0"/>
+    <reg name="r1" bitsize="16" type="int16"/>
+    <reg name="r2" bitsize="16" type="int16"/>
+    <reg name="r3" bitsize="16" type="int16"/>
+    <reg name="r4" bitsize="16" type="int16"/>
+    <reg name="r5" bitsize="16" type="int16"/>
+    <reg name="r6" bitsize="16" type="data_ptr"/>
+    <reg name="r7" bitsize="16" type="code_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="psr" bitsize="16" type="uint16"/>
+  </feature>
+</target>
+"#;
+const REG_PC: usize = 8;
+const REG_PSR: usize = 9;
+
+pub struct GdbServer {
+    listener: TcpListener,
+}
+
+impl GdbServer {
+    /// Listens on `127.0.0.1:port`. Port 0 picks a free port, see [`GdbServer::local_addr`].
+    pub fn bind(port: u16) -> io::Result<Self> {
+        Ok(Self { listener: TcpListener::bind((Ipv4Addr::LOCALHOST, port))? })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Waits for a client and serves it until it detaches, kills the program or
+    /// disconnects.
+    pub fn serve(&self, vm: &mut Vm) -> io::Result<()> {
+        let (stream, _) = self.listener.accept()?;
+        stream.set_nodelay(true)?;
+        Session {
+            vm,
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            no_ack: false,
+            exited: false,
+        }
+        .run()
+    }
+}
+
+enum Incoming {
+    Packet(String),
+    Interrupt,
+}
+
+struct Session<'a> {
+    vm: &'a mut Vm,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    no_ack: bool,
+    /// The program halted; the client has been told it exited
+    exited: bool,
+}
+
+impl Session<'_> {
+    fn run(&mut self) -> io::Result<()> {
+        while let Some(incoming) = self.receive()? {
+            let Incoming::Packet(packet) = incoming else {
+                continue;
+            };
+            match packet.as_str() {
+                "D" => return self.send("OK"),
+                "k" => return Ok(()),
+                "QStartNoAckMode" => {
+                    self.send("OK")?;
+                    self.no_ack = true;
+                }
+                _ => {
+                    let reply = self.handle(&packet);
+                    self.send(&reply)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the next packet or interrupt byte, acknowledging packets and asking
+    /// for corrupted ones to be resent. Returns `None` when the client disconnects.
+    fn receive(&mut self) -> io::Result<Option<Incoming>> {
+        loop {
+            let mut byte = [0];
+            if self.reader.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            match byte[0] {
+                0x03 => return Ok(Some(Incoming::Interrupt)),
+                b'$' => {}
+                _ => continue, // acks and line noise
+            }
+
+            let mut body = Vec::new();
+            self.reader.read_until(b'#', &mut body)?;
+            if body.pop() != Some(b'#') {
+                return Ok(None);
+            }
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|c| u8::from_str_radix(c, 16).ok());
+            let valid = expected == Some(checksum_of(&body));
+            if !self.no_ack {
+                self.writer.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(Incoming::Packet(String::from_utf8_lossy(&body).into_owned())));
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.writer.write_all(packet.as_bytes())?;
+        self.writer.flush()
+    }
+
+    /// Returns `true` if the client sent Ctrl-C while the program was running.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.reader.get_ref().set_nonblocking(true)?;
+        let pending = match self.reader.fill_buf() {
+            Ok(buf) => buf.first().copied(),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => None,
+            Err(e) => return Err(e),
+        };
+        self.reader.get_ref().set_nonblocking(false)?;
+        if pending == Some(0x03) {
+            self.reader.consume(1);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn handle(&mut self, packet: &str) -> String {
+        let Some(command) = packet.get(..1) else {
+            return String::new();
+        };
+        let args = &packet[1..];
+        let reply = match command {
+            "?" => Some(if self.exited { "W00".to_string() } else { "S05".to_string() }),
+            "g" => Some((0..NUM_REGISTERS).map(|r| format!("{:04x}", self.register(r))).collect()),
+            "G" => self.write_registers(args),
+            "p" => usize::from_str_radix(args, 16)
+                .ok()
+                .filter(|&r| r < NUM_REGISTERS)
+                .map(|r| format!("{:04x}", self.register(r))),
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "s" | "c" => Some(self.resume(command == "s", args)),
+            "Z" | "z" => self.breakpoint(command == "Z", args),
+            "H" | "T" => Some("OK".to_string()),
+            "q" => query(args),
+            _ => Some(String::new()),
+        };
+        reply.unwrap_or_else(|| "E01".to_string())
+    }
+
+    fn register(&self, r: usize) -> u16 {
+        let regs = self.vm.registers();
+        match r {
+            REG_PC => regs.get_pc(),
+            REG_PSR => regs.get_psr(),
+            r => regs.get(r),
+        }
+    }
+
+    fn set_register(&mut self, r: usize, value: u16) {
+        let regs = self.vm.registers_mut();
+        match r {
+            REG_PC => regs.set_pc(value),
+            REG_PSR => regs.set_psr(value),
+            r => {
+                // keep the condition code: a debugger write is not an instruction result
+                let cond = regs.get_cond_flag();
+                regs.set(r, value);
+                regs.set_cond_flag(cond);
+            }
+        }
+    }
+
+    fn write_registers(&mut self, args: &str) -> Option<String> {
+        let values = parse_words(args)?;
+        if values.len() != NUM_REGISTERS {
+            return None;
+        }
+        for (r, value) in values.into_iter().enumerate() {
+            self.set_register(r, value);
+        }
+        Some("OK".to_string())
+    }
+
+    fn write_register(&mut self, args: &str) -> Option<String> {
+        let (r, value) = args.split_once('=')?;
+        let r = usize::from_str_radix(r, 16).ok().filter(|&r| r < NUM_REGISTERS)?;
+        let [value] = parse_words(value)?[..] else {
+            return None;
+        };
+        self.set_register(r, value);
+        Some("OK".to_string())
+    }
+
+    fn read_memory(&mut self, args: &str) -> Option<String> {
+        let (addr, len) = parse_address_length(args)?;
+        let mut out = String::new();
+        for i in 0..len {
+            let _ = write!(out, "{:04x}", self.vm.memory().peek(addr.wrapping_add(i as u16)));
+        }
+        Some(out)
+    }
+
+    fn write_memory(&mut self, args: &str) -> Option<String> {
+        let (range, data) = args.split_once(':')?;
+        let (addr, len) = parse_address_length(range)?;
+        let words = parse_words(data)?;
+        if words.len() != len {
+            return None;
+        }
+        // a plain store: poking a device register must not print or halt. The PSR
+        // lives in the registers, which would overwrite its memory copy.
+        for (i, word) in words.into_iter().enumerate() {
+            let address = addr.wrapping_add(i as u16);
+            self.vm.memory_mut().load_words(address, &[word]);
+            if address == MR_PSR {
+                self.set_register(REG_PSR, word);
+            }
+        }
+        Some("OK".to_string())
+    }
+
+    /// Handles `s`/`c`, with an optional address to resume from.
+    fn resume(&mut self, single_step: bool, args: &str) -> String {
+        if self.exited {
+            return "W00".to_string();
+        }
+        if !args.is_empty() {
+            match u16::from_str_radix(args, 16) {
+                Ok(addr) => self.vm.registers_mut().set_pc(addr),
+                Err(_) => return "E01".to_string(),
+            }
+        }
+        let reason = if single_step {
+            self.vm.step()
+        } else {
+            match self.continue_interruptibly() {
+                Ok(Some(reason)) => Some(reason),
+                Ok(None) => return "S02".to_string(),
+                Err(_) => return "E02".to_string(),
+            }
+        };
+        self.stop_reply(reason)
+    }
+
+    /// Runs until the program stops, or returns `None` if the client interrupted it.
+    fn continue_interruptibly(&mut self) -> io::Result<Option<StopReason>> {
+        let mut first = true;
+        loop {
+            let pc = self.vm.registers().get_pc();
+            // run_for skips the breakpoint check on its first instruction, so a
+            // breakpoint landing on a chunk boundary is checked here
+            if !first && self.vm.breakpoints().any(|b| b == pc) {
+                return Ok(Some(StopReason::Breakpoint(pc)));
+            }
+            first = false;
+            if let Some(reason) = self.vm.run_for(INTERRUPT_POLL_INTERVAL) {
+                return Ok(Some(reason));
+            }
+            if self.interrupted()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn stop_reply(&mut self, reason: Option<StopReason>) -> String {
+        match reason {
+            None | Some(StopReason::Breakpoint(_)) | Some(StopReason::IoWait) => "S05".to_string(),
+            Some(StopReason::Halted | StopReason::MachineHalted) => {
+                self.exited = true;
+                "W00".to_string()
+            }
+            Some(StopReason::IllegalOpcode { .. } | StopReason::PrivilegeViolation { .. }) => "S04".to_string(),
+            // SIGXCPU and SIGALRM
+            Some(StopReason::InstructionLimit) => "S18".to_string(),
+            Some(StopReason::Timeout) => "S0E".to_string(),
+            Some(StopReason::Watchpoint { hit, .. }) => {
+                let access = self
+                    .vm
+                    .memory()
+                    .watchpoints()
+                    .iter()
+                    .any(|w| w.kind == WatchKind::Access && (w.start..=w.end).contains(&hit.address));
+                let name = match hit.kind {
+                    _ if access => "awatch",
+                    WatchKind::Read => "rwatch",
+                    _ => "watch",
+                };
+                format!("T05{}:{:04x};", name, hit.address)
+            }
+        }
+    }
+
+    /// Handles `Z`/`z` packets: `type,addr,kind`.
+    fn breakpoint(&mut self, insert: bool, args: &str) -> Option<String> {
+        let mut fields = args.split(',');
+        let kind = fields.next()?;
+        let addr = u16::from_str_radix(fields.next()?, 16).ok()?;
+        let len = fields.next().and_then(|k| u16::from_str_radix(k, 16).ok()).unwrap_or(1).max(1);
+        let watch = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.vm.add_breakpoint(addr);
+                } else {
+                    self.vm.remove_breakpoint(addr);
+                }
+                return Some("OK".to_string());
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return Some(String::new()),
+        };
+        let watchpoint = Watchpoint { start: addr, end: addr.checked_add(len - 1)?, kind: watch };
+        if insert {
+            self.vm.add_watchpoint(watchpoint);
+        } else {
+            self.vm.memory_mut().remove_watchpoint(&watchpoint);
+        }
+        Some("OK".to_string())
+    }
+}
+
+/// Answers general queries (`q` packets).
+fn query(args: &str) -> Option<String> {
+    let reply = match args.split(':').next().unwrap_or_default() {
+        "Supported" => "PacketSize=4000;QStartNoAckMode+;hwbreak+;qXfer:features:read+",
+        "Attached" => "1",
+        "C" => "QC1",
+        "fThreadInfo" => "m1",
+        "sThreadInfo" => "l",
+        "Xfer" => return read_features(args),
+        _ => "",
+    };
+    Some(reply.to_string())
+}
+
+/// Serves `Xfer:features:read:target.xml:offset,length` from [`TARGET_XML`]: `m`
+/// and a chunk while more follows, `l` and the rest at the end.
+fn read_features(args: &str) -> Option<String> {
+    let (offset, len) = args.strip_prefix("Xfer:features:read:target.xml:")?.split_once(',')?;
+    let (offset, len) = (usize::from_str_radix(offset, 16).ok()?, usize::from_str_radix(len, 16).ok()?);
+    let rest = TARGET_XML.get(offset..)?;
+    Some(match rest.get(..len) {
+        Some(chunk) if chunk.len() < rest.len() => format!("m{}", chunk),
+        _ => format!("l{}", rest),
+    })
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+/// Parses `addr,length`, both hex, refusing lengths over [`MAX_MEMORY_WORDS`].
+fn parse_address_length(args: &str) -> Option<(u16, usize)> {
+    let (addr, len) = args.split_once(',')?;
+    let len = usize::from_str_radix(len, 16).ok().filter(|&len| len <= MAX_MEMORY_WORDS)?;
+    Some((u16::from_str_radix(addr, 16).ok()?, len))
+}
+
+/// Parses a run of 4-hex-digit words.
+fn parse_words(hex: &str) -> Option<Vec<u16>> {
+    if !hex.len().is_multiple_of(4) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(4)
+        .map(|i| u16::from_str_radix(&hex[i..i + 4], 16).ok())
+        .collect()
+}
@@ -0,0 +1,139 @@
+//! Execution history for stepping backwards.
+//!
+//! While history is on (see [`Vm::set_history_limit`](crate::Vm::set_history_limit)),
+//! every executed instruction leaves an [`Undo`] record holding what it changed:
+//! the old values of the registers it wrote, the PC, PSR and saved stack pointers
+//! before it ran, and the memory stores it (or an interrupt taken after it) made.
+//! [`History`] keeps the most recent records in a bounded ring buffer.
+//!
+//! Undoing cannot take back console I/O: characters stay printed, and keys read
+//! stay consumed.
+
+use std::collections::VecDeque;
+
+use crate::memory::MemoryWrite;
+
+/// What one instruction changed, with the values from before it ran.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Undo {
+    /// Address of the instruction
+    pub pc: u16,
+    pub instr: u16,
+    /// Old values of the general purpose registers the instruction changed
+    pub registers: Vec<(usize, u16)>,
+    pub psr: u16,
+    pub saved_ssp: u16,
+    pub saved_usp: u16,
+    /// Stores in the order they happened
+    pub writes: Vec<MemoryWrite>,
+}
+
+/// The most recent [`Undo`] records, oldest first.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    records: VecDeque<Undo>,
+    limit: usize,
+}
+
+impl History {
+    /// Creates a history that keeps at most `limit` records.
+    pub fn new(limit: usize) -> Self {
+        Self { records: VecDeque::new(), limit }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        while self.records.len() > limit {
+            self.records.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Adds a record, forgetting the oldest one if the history is full.
+    pub fn push(&mut self, undo: Undo) {
+        if self.limit == 0 {
+            return;
+        }
+        if self.records.len() == self.limit {
+            self.records.pop_front();
+        }
+        self.records.push_back(undo);
+    }
+
+    /// Removes the most recent record.
+    pub fn pop(&mut self) -> Option<Undo> {
+        self.records.pop_back()
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+
+    /// Records from the most recent to the oldest.
+    pub fn iter(&self) -> impl Iterator<Item = &Undo> {
+        self.records.iter().rev()
+    }
+
+    /// The most recent recorded store to `address`, and the instruction that made it.
+    pub fn last_write_to(&self, address: u16) -> Option<(&Undo, MemoryWrite)> {
+        self.iter().find_map(|undo| {
+            let write = undo.writes.iter().rev().find(|write| write.address == address)?;
+            Some((undo, *write))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn undo(pc: u16, writes: &[(u16, u16, u16)]) -> Undo {
+        Undo {
+            pc,
+            instr: 0,
+            registers: Vec::new(),
+            psr: 0x8002,
+            saved_ssp: 0x3000,
+            saved_usp: 0,
+            writes: writes.iter().map(|&(address, old, new)| MemoryWrite { address, old, new }).collect(),
+        }
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        let mut history = History::new(2);
+        for pc in 0x3000..0x3003 {
+            history.push(undo(pc, &[]));
+        }
+        assert_eq!(history.iter().map(|u| u.pc).collect::<Vec<_>>(), [0x3002, 0x3001]);
+
+        history.set_limit(1);
+        assert_eq!(history.pop().map(|u| u.pc), Some(0x3002));
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn test_last_write_to() {
+        let mut history = History::new(10);
+        history.push(undo(0x3000, &[(0x4000, 0, 1)]));
+        history.push(undo(0x3001, &[(0x4001, 0, 7)]));
+        history.push(undo(0x3002, &[(0x4000, 1, 2), (0x4000, 2, 3)]));
+
+        let (by, write) = history.last_write_to(0x4000).unwrap();
+        assert_eq!(by.pc, 0x3002);
+        assert_eq!(write, MemoryWrite { address: 0x4000, old: 2, new: 3 });
+        assert_eq!(history.last_write_to(0x4001).unwrap().0.pc, 0x3001);
+        assert!(history.last_write_to(0x4002).is_none());
+    }
+}
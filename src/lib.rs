@@ -0,0 +1,32 @@
+//! LC-3 Virtual Machine Implementation
+//!
+//! This crate provides the building blocks of the LC-3 virtual machine: memory,
+//! registers, instruction and trap handlers, and the [`Vm`] execution core that
+//! ties them together. The `lc3_vm` binary is a thin terminal front-end over [`Vm`].
+
+pub mod assembler;
+pub mod cli;
+pub mod console;
+pub mod coverage;
+pub mod debugger;
+pub mod disasm;
+pub mod gdb;
+pub mod history;
+pub mod loader;
+pub mod memory;
+pub mod profile;
+pub mod register;
+pub mod snapshot;
+pub mod symbols;
+pub mod trace;
+pub mod trapcode;
+pub mod utils;
+pub mod opcode;
+pub mod vm;
+
+// the helpers the integration tests share, for unit tests that need files
+#[cfg(test)]
+#[path = "../tests/common/mod.rs"]
+mod test_common;
+
+pub use vm::{StopReason, Vm};
@@ -0,0 +1,558 @@
+//! Program loading.
+//!
+//! An [`Image`] collects one or more [`Segment`]s — blocks of words with a load
+//! address — from program files, checks that they fit in memory without
+//! overlapping, and knows where execution should start. Files come in several
+//! [`Format`]s:
+//!
+//! * `.obj` files whose first word is the origin, as the assembler and `lc3as`
+//!   write them, or raw headerless words loaded at an origin chosen by the caller
+//! * lc3tools-style text: one word per line as 4 hex digits (`.hex`) or 16 binary
+//!   digits (`.bin`), the first line being the origin; `;` starts a comment
+//! * Intel HEX, whose byte addresses are halved to give word addresses; it may
+//!   hold several segments
+//!
+//! [`encode`] writes segments back out in any of them, for `lc3_vm convert`.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::memory::Memory;
+
+/// How the bytes of a program file are laid out. Binary words are big-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The first word is the load address, the rest are the program
+    Obj,
+    /// Every word is program, loaded at `origin`
+    Raw { origin: u16 },
+    /// Text, one hex word per line, origin first
+    Hex,
+    /// Text, one 16-digit binary word per line, origin first
+    Bin,
+    IntelHex,
+    /// Decided by [`Format::detect`] when the file is read
+    Auto,
+}
+
+impl Format {
+    /// Picks the format of a file: a known extension decides it as in
+    /// [`Format::for_output`], and anything else is recognised by its contents,
+    /// falling back to [`Format::Obj`].
+    pub fn detect(path: impl AsRef<Path>, bytes: &[u8]) -> Self {
+        if let Some(format) = Self::for_output(&path) {
+            return format;
+        }
+        let Ok(text) = std::str::from_utf8(bytes) else {
+            return Self::Obj;
+        };
+        let lines: Vec<&str> = text_lines(text).map(|(_, line)| line).collect();
+        if text.trim_start().starts_with(':') {
+            Self::IntelHex
+        } else if lines.is_empty() {
+            Self::Obj
+        } else if lines.iter().all(|line| line.len() == 16 && line.bytes().all(|b| b == b'0' || b == b'1')) {
+            Self::Bin
+        } else if lines.iter().all(|line| (1..=4).contains(&line.len()) && line.bytes().all(|b| b.is_ascii_hexdigit())) {
+            Self::Hex
+        } else {
+            Self::Obj
+        }
+    }
+
+    /// The format to write a file in, by its extension: `.obj`, `.hex`, `.bin`,
+    /// or `.ihex`/`.ihx` for Intel HEX.
+    pub fn for_output(path: impl AsRef<Path>) -> Option<Self> {
+        let ext = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "obj" => Some(Self::Obj),
+            "hex" => Some(Self::Hex),
+            "bin" => Some(Self::Bin),
+            "ihex" | "ihx" => Some(Self::IntelHex),
+            _ => None,
+        }
+    }
+}
+
+/// Consecutive words loaded at `origin`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    /// Where the words came from, for error messages
+    pub name: String,
+    pub origin: u16,
+    pub words: Vec<u16>,
+}
+
+impl Segment {
+    fn new(name: &str, origin: u16, words: Vec<u16>) -> Result<Self, LoadError> {
+        if origin as usize + words.len() > 0x10000 {
+            return Err(LoadError::Wraps { name: name.to_string(), origin: origin.into(), len: words.len() });
+        }
+        Ok(Self { name: name.to_string(), origin, words })
+    }
+
+    /// The last address the segment occupies, or `None` if it has no words.
+    pub fn end(&self) -> Option<u16> {
+        (!self.words.is_empty()).then(|| self.origin + (self.words.len() - 1) as u16)
+    }
+}
+
+/// Parses a program file's contents. `name` is used in error messages and, for
+/// [`Format::Auto`], to detect the format.
+pub fn parse(name: &str, bytes: &[u8], format: Format) -> Result<Vec<Segment>, LoadError> {
+    match format {
+        Format::Auto => parse(name, bytes, Format::detect(name, bytes)),
+        Format::Obj | Format::Raw { .. } => {
+            if !bytes.len().is_multiple_of(2) {
+                return Err(LoadError::OddLength { name: name.to_string(), len: bytes.len() });
+            }
+            let mut words: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            let origin = match format {
+                Format::Raw { origin } => origin,
+                _ if words.is_empty() => return Err(LoadError::MissingOrigin { name: name.to_string() }),
+                _ => words.remove(0),
+            };
+            Ok(vec![Segment::new(name, origin, words)?])
+        }
+        Format::Hex | Format::Bin => {
+            let text = as_text(name, bytes)?;
+            let radix = if format == Format::Hex { 16 } else { 2 };
+            let mut words = Vec::new();
+            for (line, digits) in text_lines(text) {
+                let word = u16::from_str_radix(digits, radix)
+                    .map_err(|_| LoadError::syntax(name, line, format!("invalid word '{}'", digits)))?;
+                words.push(word);
+            }
+            if words.is_empty() {
+                return Err(LoadError::MissingOrigin { name: name.to_string() });
+            }
+            let origin = words.remove(0);
+            Ok(vec![Segment::new(name, origin, words)?])
+        }
+        Format::IntelHex => parse_intel_hex(name, as_text(name, bytes)?),
+    }
+}
+
+/// Reads and parses a program file.
+pub fn read(path: impl AsRef<Path>, format: Format) -> Result<Vec<Segment>, LoadError> {
+    let name = path.as_ref().display().to_string();
+    let bytes = fs::read(path).map_err(|error| LoadError::Io { name: name.clone(), error })?;
+    parse(&name, &bytes, format)
+}
+
+fn as_text<'a>(name: &str, bytes: &'a [u8]) -> Result<&'a str, LoadError> {
+    std::str::from_utf8(bytes).map_err(|_| LoadError::syntax(name, 1, "file is not text".to_string()))
+}
+
+/// Non-blank lines with comments stripped, numbered from 1.
+fn text_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.split(';').next().unwrap_or("").trim()))
+        .filter(|(_, line)| !line.is_empty())
+}
+
+fn parse_intel_hex(name: &str, text: &str) -> Result<Vec<Segment>, LoadError> {
+    let mut bytes = BTreeMap::new();
+    let mut base = 0u32;
+    for (line, record) in text_lines(text) {
+        let error = |message: &str| LoadError::syntax(name, line, message.to_string());
+        let digits = record.strip_prefix(':').ok_or_else(|| error("record does not start with ':'"))?;
+        if digits.len() % 2 != 0 || digits.len() < 10 {
+            return Err(error("record is too short"));
+        }
+        let data: Vec<u8> = (0..digits.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digits[i..i + 2], 16))
+            .collect::<Result<_, _>>()
+            .map_err(|_| error("invalid hex digits"))?;
+        if data.len() != data[0] as usize + 5 {
+            return Err(error("record length does not match its byte count"));
+        }
+        if data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(error("checksum mismatch"));
+        }
+        let address = u16::from_be_bytes([data[1], data[2]]) as u32;
+        let payload = &data[4..data.len() - 1];
+        match data[3] {
+            0x00 => {
+                // base is at most xFFFF0000, so only adding the payload can overflow
+                let start = base + address;
+                if start.checked_add(payload.len() as u32).is_none_or(|end| end > 0x20000) {
+                    let end = start as u64 + payload.len() as u64;
+                    return Err(LoadError::Wraps {
+                        name: name.to_string(),
+                        origin: start / 2,
+                        len: (end.div_ceil(2) - start as u64 / 2) as usize,
+                    });
+                }
+                for (i, byte) in payload.iter().enumerate() {
+                    bytes.insert(start + i as u32, *byte);
+                }
+            }
+            0x01 => break,
+            0x02 if payload.len() == 2 => base = (u16::from_be_bytes([payload[0], payload[1]]) as u32) << 4,
+            0x04 if payload.len() == 2 => base = (u16::from_be_bytes([payload[0], payload[1]]) as u32) << 16,
+            // start addresses do not apply to the LC-3
+            0x03 | 0x05 => {}
+            _ => return Err(error("unsupported record type")),
+        }
+    }
+
+    // pair bytes into big-endian words, then split into runs of consecutive words
+    let mut words: BTreeMap<u32, u16> = BTreeMap::new();
+    for (&address, &byte) in &bytes {
+        let word = words.entry(address / 2).or_insert(0);
+        *word |= if address % 2 == 0 { (byte as u16) << 8 } else { byte as u16 };
+    }
+    let mut segments: Vec<Segment> = Vec::new();
+    for (address, word) in words {
+        // every data record was checked to end by xFFFF
+        let address = address as u16;
+        match segments.last_mut() {
+            Some(segment) if segment.end().map(|end| end as u32 + 1) == Some(address as u32) => {
+                segment.words.push(word);
+            }
+            _ => segments.push(Segment::new(name, address, vec![word])?),
+        }
+    }
+    Ok(segments)
+}
+
+/// Formats segments as a program file. [`Format::Obj`], [`Format::Hex`] and
+/// [`Format::Bin`] hold a single segment; [`Format::Raw`] also drops its origin.
+pub fn encode(segments: &[Segment], format: Format) -> Result<Vec<u8>, String> {
+    let single = || match segments {
+        [segment] => Ok(segment),
+        _ => Err(format!("this format holds one segment, not {}", segments.len())),
+    };
+    let mut out = String::new();
+    match format {
+        Format::Obj | Format::Raw { .. } => {
+            let segment = single()?;
+            let header = if format == Format::Obj { Some(segment.origin) } else { None };
+            let words = header.iter().chain(&segment.words);
+            return Ok(words.flat_map(|word| word.to_be_bytes()).collect());
+        }
+        Format::Hex | Format::Bin => {
+            let segment = single()?;
+            for word in std::iter::once(&segment.origin).chain(&segment.words) {
+                let _ = match format {
+                    Format::Hex => writeln!(out, "{:04X}", word),
+                    _ => writeln!(out, "{:016b}", word),
+                };
+            }
+        }
+        Format::IntelHex => {
+            let mut upper = 0;
+            for segment in segments {
+                let bytes: Vec<u8> = segment.words.iter().flat_map(|word| word.to_be_bytes()).collect();
+                let start = segment.origin as u32 * 2;
+                let mut offset = 0;
+                while offset < bytes.len() {
+                    let address = start + offset as u32;
+                    if address >> 16 != upper {
+                        upper = address >> 16;
+                        intel_hex_record(&mut out, 0, 0x04, &(upper as u16).to_be_bytes());
+                    }
+                    // records stay within one 64K block and hold up to 16 bytes
+                    let len = 16.min(bytes.len() - offset).min(0x10000 - (address & 0xFFFF) as usize);
+                    intel_hex_record(&mut out, address as u16, 0x00, &bytes[offset..offset + len]);
+                    offset += len;
+                }
+            }
+            intel_hex_record(&mut out, 0, 0x01, &[]);
+        }
+        Format::Auto => return Err("no output format given".to_string()),
+    }
+    Ok(out.into_bytes())
+}
+
+fn intel_hex_record(out: &mut String, address: u16, kind: u8, data: &[u8]) {
+    let mut record = vec![data.len() as u8];
+    record.extend(address.to_be_bytes());
+    record.push(kind);
+    record.extend(data);
+    let checksum = record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg();
+    out.push(':');
+    for byte in record.iter().chain([&checksum]) {
+        let _ = write!(out, "{:02X}", byte);
+    }
+    out.push('\n');
+}
+
+/// Why a program could not be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    Io { name: String, error: io::Error },
+    /// Words are two bytes, so a file with a trailing byte is truncated or corrupt
+    OddLength { name: String, len: usize },
+    /// A file too short to hold its origin word
+    MissingOrigin { name: String },
+    /// A text format file has a malformed line
+    Syntax { name: String, line: usize, message: String },
+    /// The words would run past xFFFF. An Intel HEX record's `origin` may itself
+    /// lie beyond xFFFF.
+    Wraps { name: String, origin: u32, len: usize },
+    /// Two segments claim the addresses `start..=end`
+    Overlap { first: String, second: String, start: u16, end: u16 },
+    /// A segment reaches outside the addresses it may be loaded into
+    OutsideRegion { name: String, start: u16, end: u16, region_start: u16, region_end: u16 },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { name, error } => write!(f, "{}: {}", name, error),
+            Self::OddLength { name, len } => {
+                write!(f, "{}: file is {} bytes long, not a whole number of words", name, len)
+            }
+            Self::MissingOrigin { name } => write!(f, "{}: file is too short to contain an origin", name),
+            Self::Syntax { name, line, message } => write!(f, "{}:{}: {}", name, line, message),
+            Self::Wraps { name, origin, len } => {
+                write!(f, "{}: {} words at x{:04X} run past xFFFF", name, len, origin)
+            }
+            Self::Overlap { first, second, start, end } => {
+                write!(f, "{}: x{:04X}-x{:04X} overlaps {}", second, start, end, first)
+            }
+            Self::OutsideRegion { name, start, end, region_start, region_end } => write!(
+                f,
+                "{}: x{:04X}-x{:04X} does not fit in x{:04X}-x{:04X}",
+                name, start, end, region_start, region_end
+            ),
+        }
+    }
+}
+
+impl LoadError {
+    fn syntax(name: &str, line: usize, message: String) -> Self {
+        Self::Syntax { name: name.to_string(), line, message }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Segments to be loaded together into one memory image.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Image {
+    segments: Vec<Segment>,
+}
+
+impl Image {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a segment, rejecting it if it overlaps one already added.
+    pub fn add(&mut self, segment: Segment) -> Result<(), LoadError> {
+        if let Some(end) = segment.end() {
+            for other in &self.segments {
+                let Some(other_end) = other.end() else {
+                    continue;
+                };
+                let start = segment.origin.max(other.origin);
+                let overlap_end = end.min(other_end);
+                if start <= overlap_end {
+                    return Err(LoadError::Overlap {
+                        first: other.name.clone(),
+                        second: segment.name,
+                        start,
+                        end: overlap_end,
+                    });
+                }
+            }
+        }
+        self.segments.push(segment);
+        Ok(())
+    }
+
+    /// Reads a program file and adds its segments.
+    pub fn add_file(&mut self, path: impl AsRef<Path>, format: Format) -> Result<(), LoadError> {
+        for segment in read(path, format)? {
+            self.add(segment)?;
+        }
+        Ok(())
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Where execution starts: the origin of the first segment added.
+    pub fn entry(&self) -> Option<u16> {
+        self.segments.first().map(|segment| segment.origin)
+    }
+
+    /// Checks that every segment lies within `region_start..=region_end`.
+    pub fn check_within(&self, region_start: u16, region_end: u16) -> Result<(), LoadError> {
+        for segment in &self.segments {
+            let Some(end) = segment.end() else {
+                continue;
+            };
+            if segment.origin < region_start || end > region_end {
+                return Err(LoadError::OutsideRegion {
+                    name: segment.name.clone(),
+                    start: segment.origin,
+                    end,
+                    region_start,
+                    region_end,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies every segment into memory. Device registers are stored to directly,
+    /// without their side effects.
+    pub fn write_to(&self, memory: &mut Memory) {
+        for segment in &self.segments {
+            memory.load_words(segment.origin, &segment.words);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common::TempFile;
+
+    #[test]
+    fn test_load_program_with_header() {
+        let mut mem = Memory::new();
+
+        let binary: Vec<u8> = vec![
+            0x40, 0x00, // origin = 0x4000
+            0x12, 0x34, // instruction 1
+            0xAB, 0xCD  // instruction 2
+        ];
+
+        let file = TempFile::new("with_header.obj");
+        std::fs::write(file.path(), &binary).unwrap();
+        let mut image = Image::new();
+        image.add_file(file.path(), Format::Obj).unwrap();
+        image.write_to(&mut mem);
+
+        assert_eq!(image.entry(), Some(0x4000));
+        assert_eq!(mem.read(0x4000), 0x1234);
+        assert_eq!(mem.read(0x4001), 0xABCD);
+    }
+
+    #[test]
+    fn test_load_program_without_header() {
+        let mut mem = Memory::new();
+
+        let binary: Vec<u8> = vec![
+            0x12, 0x34,
+            0x56, 0x78
+        ];
+
+        let file = TempFile::new("no_header.obj");
+        std::fs::write(file.path(), &binary).unwrap();
+        let mut image = Image::new();
+        image.add_file(file.path(), Format::Raw { origin: 0x3000 }).unwrap();
+        image.write_to(&mut mem);
+
+        assert_eq!(mem.read(0x3000), 0x1234);
+        assert_eq!(mem.read(0x3001), 0x5678);
+    }
+
+    #[test]
+    fn test_malformed_files() {
+        assert!(matches!(
+            parse("odd.obj", &[0x30, 0x00, 0x12], Format::Obj),
+            Err(LoadError::OddLength { len: 3, .. })
+        ));
+        assert!(matches!(parse("empty.obj", &[], Format::Obj), Err(LoadError::MissingOrigin { .. })));
+
+        let err = parse("end.obj", &[0xFF, 0xFF, 0, 1, 0, 2], Format::Obj).unwrap_err();
+        assert_eq!(err.to_string(), "end.obj: 2 words at xFFFF run past xFFFF");
+        assert!(parse("last.obj", &[0xFF, 0xFF, 0, 1], Format::Obj).is_ok());
+
+        let err = parse("prog.hex", b"3000\n12G4\n", Format::Hex).unwrap_err();
+        assert_eq!(err.to_string(), "prog.hex:2: invalid word '12G4'");
+        // the extension picks the format, so a bad line is not read as an object file
+        let err = parse("prog.hex", b"3000\n12G4\nF025\n", Format::Auto).unwrap_err();
+        assert_eq!(err.to_string(), "prog.hex:2: invalid word '12G4'");
+        let err = parse("prog.BIN", b"0011000000000000\n0001002000110100\n", Format::Auto).unwrap_err();
+        assert!(err.to_string().starts_with("prog.BIN:2: "), "{}", err);
+        let err = parse("prog.ihex", b":0100000012EE\n", Format::IntelHex).unwrap_err();
+        assert_eq!(err.to_string(), "prog.ihex:1: checksum mismatch");
+    }
+
+    #[test]
+    fn test_text_formats() {
+        let segment = Segment { name: "prog".to_string(), origin: 0x3000, words: vec![0x1234, 0xF025] };
+        let hex = encode(std::slice::from_ref(&segment), Format::Hex).unwrap();
+        assert_eq!(hex, b"3000\n1234\nF025\n");
+        let bin = encode(std::slice::from_ref(&segment), Format::Bin).unwrap();
+        assert_eq!(bin, b"0011000000000000\n0001001000110100\n1111000000100101\n");
+
+        for (name, bytes) in [("prog.hex", hex), ("prog.bin", bin)] {
+            assert_eq!(parse(name, &bytes, Format::Auto).unwrap()[0].words, segment.words);
+        }
+        let commented = parse("prog.txt", b"; origin\nx3000\n", Format::Hex);
+        assert!(commented.is_err()); // only bare digits are words
+        let commented = parse("prog.txt", b"3000 ; origin\n\n1234\n", Format::Auto).unwrap();
+        assert_eq!(commented[0].words, [0x1234]);
+    }
+
+    #[test]
+    fn test_intel_hex() {
+        let segments = vec![
+            Segment { name: "a".to_string(), origin: 0x3000, words: (0..10).collect() },
+            Segment { name: "b".to_string(), origin: 0xFFFF, words: vec![0xF025] },
+        ];
+        let bytes = encode(&segments, Format::IntelHex).unwrap();
+        let text = String::from_utf8(bytes.clone()).unwrap();
+        assert!(text.starts_with(":1060000000000001000200030004000500060007"));
+        assert!(text.contains(":020000040001F9\n")); // xFFFF is at byte address x1FFFE
+        assert!(text.ends_with(":00000001FF\n"));
+
+        let parsed = parse("prog.ihex", &bytes, Format::Auto).unwrap();
+        assert_eq!(parsed.iter().map(|s| (s.origin, s.words.clone())).collect::<Vec<_>>(), [
+            (0x3000, (0..10).collect::<Vec<_>>()),
+            (0xFFFF, vec![0xF025]),
+        ]);
+        assert!(encode(&segments, Format::Obj).is_err());
+    }
+
+    #[test]
+    fn test_intel_hex_past_xffff() {
+        // x0001 upper address: the record starts at word xFFFE and needs three words
+        let err = parse("prog.ihex", b":020000040001F9\n:06FFFC001122334455669A\n", Format::IntelHex).unwrap_err();
+        assert_eq!(err.to_string(), "prog.ihex: 3 words at xFFFE run past xFFFF");
+
+        // the highest upper address and offset: adding them up must not overflow
+        let err = parse("prog.ihex", b":02000004FFFFFC\n:02FFFF00ABCD88\n", Format::IntelHex).unwrap_err();
+        assert_eq!(err.to_string(), "prog.ihex: 2 words at x7FFFFFFF run past xFFFF");
+    }
+
+    #[test]
+    fn test_segments() {
+        let segment = |name: &str, origin, len| Segment { name: name.to_string(), origin, words: vec![0; len] };
+        let mut image = Image::new();
+        image.add(segment("main.obj", 0x3000, 0x10)).unwrap();
+        image.add(segment("lib.obj", 0x3010, 0x10)).unwrap();
+
+        let err = image.add(segment("data.obj", 0x2FF0, 0x12)).unwrap_err();
+        assert_eq!(err.to_string(), "data.obj: x3000-x3001 overlaps main.obj");
+        assert_eq!(image.entry(), Some(0x3000));
+        assert_eq!(image.segments().len(), 2);
+
+        assert!(image.check_within(0x3000, 0x301F).is_ok());
+        assert!(image.check_within(0x0000, 0x2FFF).is_err());
+    }
+}
@@ -1,42 +1,317 @@
-//! LC-3 Virtual Machine Implementation
+//! LC-3 Virtual Machine binary
 //!
-//! This module provides the main execution loop and program initialization for
-//! the LC-3 virtual machine. It handles loading programs, processing instructions,
-//! and interfacing with the terminal in raw mode for proper I/O operations.
+//! `lc3_vm [run] [options] <program.obj>...` loads programs into a [`Vm`] and runs
+//! them on a [`TerminalConsole`], which puts the terminal in raw mode for proper I/O
+//! operations, or under the interactive [`Debugger`] (`--debug`) or a GDB client
+//! (`--gdb <port>`), or without a terminal on scripted input (`--headless`). See
+//! [`cli::HELP`] for the options.
+//! `lc3_vm asm <program.asm> [-o <program.obj>]` assembles a source file, writing the
+//! symbol table (`.sym`) and line map (`.dbg`) next to the object file.
+//! `lc3_vm disasm <program.obj> [--sym <program.sym>]` prints a listing of an object
+//! file, labelled from its symbol table.
+//! `lc3_vm convert <input> <output>` rewrites a program in another
+//! [`Format`], chosen by the output's extension.
 
-pub mod memory;
-pub mod register;
-pub mod trapcode;
-pub mod utils;
-pub mod opcode;
-
-use memory::Memory;
-use register::Registers;
-use opcode::OpCode;
+use lc3_vm::assembler;
+use lc3_vm::cli::{self, Command, Mode, RunOptions};
+use lc3_vm::console::{BufferConsole, Console, NullConsole, PipeConsole, TerminalConsole};
+use lc3_vm::coverage::{Coverage, CoverageFormat};
+use lc3_vm::debugger::Debugger;
+use lc3_vm::disasm;
+use lc3_vm::gdb::GdbServer;
+use lc3_vm::loader::{self, Format};
+use lc3_vm::opcode::OpCode;
+use lc3_vm::profile::Profiler;
+use lc3_vm::symbols::{DebugInfo, SymbolTable};
+use lc3_vm::trace::Tracer;
+use lc3_vm::{StopReason, Vm};
 
 use std::io::*;
-use std::io::stdout as stdout_main; 
+use std::io::stdout as stdout_main;
+use std::path::{Path, PathBuf};
 use termion::raw::*;
 
 use std::panic;
+use std::process;
 use std::thread;
 use signal_hook::{iterator::Signals, consts::SIGINT};
 
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match cli::parse(&args) {
+        Ok(Command::Help) => println!("{}\n\n{}", cli::USAGE, cli::HELP),
+        Ok(Command::Assemble { input, output }) => assemble(&input, output),
+        Ok(Command::Disassemble { input, symbols }) => disassemble(&input, symbols.as_deref()),
+        Ok(Command::Convert { input, output }) => convert(&input, &output),
+        Ok(Command::Run(options)) => match options.mode {
+            Mode::Run => run(*options),
+            Mode::Debug => debug(*options),
+            Mode::Gdb(port) => gdb(port, *options),
+            Mode::Headless => headless(*options),
+        },
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, cli::USAGE);
+            process::exit(2);
+        }
+    }
+}
+
+/// Creates a VM with the programs loaded, exiting with a message if any cannot be.
+/// The VM starts with a [`NullConsole`]; callers attach their own.
+fn load(options: &RunOptions) -> (Vm, DebugInfo) {
+    let mut vm = Vm::new(NullConsole);
+    let info = options.load(&mut vm).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    if let Some(path) = &options.trace {
+        let mut tracer = Tracer::create(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        });
+        tracer.set_debug_info(info.clone());
+        vm.set_tracer(tracer);
+    }
+    if options.profile.is_some() {
+        vm.set_profiler(Profiler::new());
+    }
+    if options.coverage.is_some() {
+        vm.set_coverage(Coverage::new());
+    }
+    // without an OS there is no handler to report a fault, so a headless run must stop
+    let headless_without_os = options.mode == Mode::Headless && !vm.os_traps();
+    vm.set_stop_on_exception(options.stop_on_exception || headless_without_os);
+    vm.set_instruction_limit(options.max_instructions);
+    vm.set_timeout(options.timeout);
+    (vm, info)
+}
+
+/// Flushes the trace, returning a message if it could not be written.
+fn finish_trace(vm: &mut Vm, options: &RunOptions) -> Option<String> {
+    match (&options.trace, vm.take_tracer().map(Tracer::finish)) {
+        (Some(path), Some(Err(e))) => Some(format!("{}: {}\n", path, e)),
+        _ => None,
+    }
+}
 
+/// Finishes the trace, profile and coverage outputs, returning a message for each
+/// that could not be written.
+fn finish_outputs(vm: &mut Vm, options: &RunOptions, info: &DebugInfo) -> Vec<String> {
+    [finish_trace(vm, options), finish_profile(vm, options, &info.symbols), finish_coverage(vm, options, info)]
+        .into_iter()
+        .flatten()
+        .collect()
+}
 
-fn main() {
+/// Writes the profile report and folded stacks, returning a message if they could
+/// not be written.
+fn finish_profile(vm: &mut Vm, options: &RunOptions, symbols: &SymbolTable) -> Option<String> {
+    let (path, profiler) = (options.profile.as_ref()?, vm.take_profiler()?);
+    let folded = format!("{}.folded", path);
+    for (file, contents) in [(path, profiler.report(symbols)), (&folded, profiler.folded(symbols))] {
+        if let Err(e) = std::fs::write(file, contents) {
+            return Some(format!("{}: {}\n", file, e));
+        }
+    }
+    None
+}
+
+/// Writes the coverage report over the programs, returning a message if it could
+/// not be written.
+fn finish_coverage(vm: &mut Vm, options: &RunOptions, info: &DebugInfo) -> Option<String> {
+    let (path, coverage) = (options.coverage.as_ref()?, vm.take_coverage()?);
+    let mut segments = Vec::new();
+    for program in &options.programs {
+        match loader::read(program, Format::Auto) {
+            Ok(read) => segments.extend(read),
+            Err(e) => return Some(format!("{}\n", e)),
+        }
+    }
+    let report = match CoverageFormat::for_path(path) {
+        CoverageFormat::Listing => coverage.listing(&segments, &info.symbols),
+        CoverageFormat::Lcov => coverage.lcov(&segments, info),
+    };
+    std::fs::write(path, report).err().map(|e| format!("{}: {}\n", path, e))
+}
+
+fn assemble(input: &str, output: Option<String>) {
+    let output = output.map_or_else(|| Path::new(input).with_extension("obj"), PathBuf::from);
+
+    let source = std::fs::read_to_string(input).unwrap_or_else(|e| {
+        eprintln!("{}: {}", input, e);
+        process::exit(1);
+    });
+    let program = assembler::assemble(&source).unwrap_or_else(|e| {
+        eprintln!("{}:{}: {}", input, e.line, e.message);
+        process::exit(1);
+    });
+    let files = [
+        (output.clone(), program.to_obj_bytes()),
+        (output.with_extension("sym"), program.symbols.to_sym().into_bytes()),
+        (output.with_extension("dbg"), program.line_map(input).to_dbg().into_bytes()),
+    ];
+    for (path, contents) in files {
+        if let Err(e) = std::fs::write(&path, contents) {
+            eprintln!("{}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+}
+
+fn disassemble(input: &str, symbols: Option<&str>) {
+    let symbols = match symbols {
+        None => DebugInfo::load_for(input).map(|info| info.symbols),
+        Some(sym) => SymbolTable::load(sym),
+    };
+    let symbols = symbols.unwrap_or_else(|e| {
+        eprintln!("failed to read symbols: {}", e);
+        process::exit(1);
+    });
+    let segments = loader::read(input, Format::Auto).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    for segment in segments {
+        print!("{}", disasm::listing(segment.origin, &segment.words, &symbols));
+    }
+}
+
+fn convert(input: &str, output: &str) {
+    let Some(format) = Format::for_output(output) else {
+        eprintln!("{}: unknown output format; use .obj, .hex, .bin or .ihex", output);
+        process::exit(1);
+    };
+    let segments = loader::read(input, Format::Auto).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    let result = loader::encode(&segments, format).and_then(|bytes| {
+        std::fs::write(output, bytes).map_err(|e| e.to_string())
+    });
+    if let Err(e) = result {
+        eprintln!("{}: {}", output, e);
+        process::exit(1);
+    }
+}
+
+fn debug(options: RunOptions) {
+    // The terminal stays in cooked mode so that commands can be typed and edited
+    let (mut vm, info) = load(&options);
+    vm.set_console(PipeConsole::new());
+    let mut debugger = Debugger::new(vm, info.clone());
+    debugger.repl();
+    let vm = debugger.vm_mut();
+    for message in finish_outputs(vm, &options, &info) {
+        eprint!("{}", message);
+    }
+}
+
+fn gdb(port: u16, options: RunOptions) {
+    let (mut vm, info) = load(&options);
+    vm.set_console(PipeConsole::new());
+    let result = GdbServer::bind(port).and_then(|server| {
+        eprintln!("Waiting for a GDB connection on {}", server.local_addr()?);
+        server.serve(&mut vm)
+    });
+    for message in finish_outputs(&mut vm, &options, &info) {
+        eprint!("{}", message);
+    }
+    if let Err(e) = result {
+        eprintln!("gdb server: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Explains why a run stopped, or returns an empty string if it simply halted.
+fn stop_message(vm: &Vm, reason: StopReason, debug_info: &DebugInfo) -> String {
+    let pc = vm.registers().get_pc();
+    match reason {
+        StopReason::IllegalOpcode { pc, instr } => {
+            format!("{:?} not implemented at {}\n", OpCode::from_instr(instr), debug_info.describe(pc))
+        }
+        StopReason::PrivilegeViolation { pc, .. } => {
+            format!("Privilege mode violation: RTI in user mode at {}\n", debug_info.describe(pc))
+        }
+        StopReason::IoWait => "Input closed while waiting for a character\n".to_string(),
+        StopReason::InstructionLimit => format!(
+            "Instruction limit reached after {} instructions at {}\n",
+            vm.instructions_executed(),
+            debug_info.describe(pc)
+        ),
+        StopReason::Timeout => format!("Timed out at {}\n", debug_info.describe(pc)),
+        StopReason::Halted | StopReason::MachineHalted | StopReason::Breakpoint(_) | StopReason::Watchpoint { .. } => String::new(),
+    }
+}
+
+/// The console of a headless run. Program output is captured for the transcript;
+/// the VM's own notices are kept apart and go to stderr.
+struct Transcript {
+    output: BufferConsole,
+    notices: BufferConsole,
+}
+
+impl Console for Transcript {
+    fn read_char(&mut self) -> Option<u8> {
+        self.output.read_char()
+    }
+
+    fn poll_char(&mut self) -> Option<u8> {
+        self.output.poll_char()
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.output.write_bytes(bytes)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+
+    fn write_notice(&mut self, text: &str) -> std::io::Result<()> {
+        self.notices.write_bytes(text.as_bytes())
+    }
+}
+
+fn headless(options: RunOptions) {
+    let input = options.input_bytes().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    let (mut vm, debug_info) = load(&options);
+    let (console, notices) = (BufferConsole::with_input(&input), BufferConsole::new());
+    vm.set_console(Transcript { output: console.clone(), notices: notices.clone() });
+
+    let reason = vm.run();
+    let output = console.take_output();
+    let written = match &options.output {
+        Some(path) => std::fs::write(path, &output).map_err(|e| format!("{}: {}", path, e)),
+        None => stdout().write_all(&output).and_then(|_| stdout().flush()).map_err(|e| e.to_string()),
+    };
+    eprint!("{}", String::from_utf8_lossy(&notices.take_output()));
+    eprint!("{}", stop_message(&vm, reason, &debug_info));
+    let mut code = cli::exit_code(reason);
+    for message in finish_outputs(&mut vm, &options, &debug_info).into_iter().chain(written.err()) {
+        eprintln!("{}", message.trim_end());
+        code = code.max(1);
+    }
+    process::exit(code);
+}
+
+fn run(options: RunOptions) {
+    // Load before touching the terminal so errors print normally
+    let (mut vm, debug_info) = load(&options);
 
     let original_hook = panic::take_hook();
     panic::set_hook(Box::new(move |panic_info| {
         let _ = stdout().into_raw_mode().unwrap().suspend_raw_mode();
         original_hook(panic_info);
     }));
-    
-    
-    let mut raw_stdout = stdout_main().into_raw_mode().unwrap();
-    let mut signals = Signals::new(&[SIGINT]).expect("Failed to register signal handler");
+
+
+    let mut console = TerminalConsole::new().unwrap();
+    let mut signals = Signals::new([SIGINT]).expect("Failed to register signal handler");
     thread::spawn(move || {
-        for _ in signals.forever() {
+        if signals.forever().next().is_some() {
             // Restore terminal settings when Ctrl+C is pressed
             let _ = stdout_main().into_raw_mode().unwrap().suspend_raw_mode();
             eprintln!("\r\nProgram terminated by user.");
@@ -44,53 +319,19 @@ fn main() {
         }
     });
 
-    write!(raw_stdout, "LC3 Virtual Machine\r\n").unwrap();
-    raw_stdout.flush().unwrap();
-
-    let mut memory = Memory::new();
-    let mut registers = Registers::new();
-    let pc : u16 = 0x3000;
-
-    let program_path = std::env::args().nth(1).expect("Usage: lc3vm <program.obj>");
-    memory.load_program(&program_path, pc).expect("Failed to load program");
-
-    registers.set_pc(pc);
-
-    'exec: loop {
-        let pc = registers.get_pc();
-        let instr = memory.read(pc);
-        registers.increment_pc();
-
-        let opcode = OpCode::from_instr(instr);
-
-        match opcode {
-            OpCode::ADD => opcode::handle_add(instr, &mut registers),
-            OpCode::AND => opcode::handle_and(instr, &mut registers),
-            OpCode::NOT => opcode::handle_not(instr, &mut registers),
-            OpCode::BR => opcode::handle_br(instr, &mut registers),
-            OpCode::JSR => opcode::handle_jsr(instr, &mut registers),
-            OpCode::LD => opcode::handle_ld(instr, &mut memory, &mut registers),
-            OpCode::LDR => opcode::handle_ldr(instr, &mut memory, &mut registers),
-            OpCode::ST => opcode::handle_st(instr, &mut memory, &mut registers),
-            OpCode::JMP => opcode::handle_jmp(instr, &mut registers),
-            OpCode::LEA => opcode::handle_lea(instr, &mut registers),
-            OpCode::STI => opcode::handle_sti(instr, &mut memory, &mut registers),
-            OpCode::LDI => opcode::handle_ldi(instr, &mut memory, &mut registers),
-            OpCode::STR => opcode::handle_str(instr, &mut memory, &mut registers),
-            OpCode::RTI => {
-                println!("RTI not implemented");
-                break 'exec;
-            }
-            OpCode::RES => {
-                println!("RES not implemented");
-                break 'exec;
-            }
-            OpCode::TRAP => {
-                let cont = trapcode::handle_trap(instr, &mut memory, &mut registers, &mut raw_stdout);
-                if !cont {
-                    break 'exec;
-                }
-            }
-        }
+    console.write_bytes(b"LC3 Virtual Machine\n").unwrap();
+    console.flush().unwrap();
+
+    vm.set_console(console);
+
+    let reason = vm.run();
+    let message = stop_message(&vm, reason, &debug_info);
+    vm.console().write_bytes(message.as_bytes()).unwrap();
+    for message in finish_outputs(&mut vm, &options, &debug_info) {
+        vm.console().write_bytes(message.as_bytes()).unwrap();
     }
-}
\ No newline at end of file
+    vm.console().flush().unwrap();
+    // dropping the VM drops its console, which restores the terminal
+    drop(vm);
+    process::exit(cli::exit_code(reason));
+}
@@ -1,64 +1,280 @@
-use std::fs::File;
-use std::io::{Read, Result};
+use crate::console::{Console, NullConsole};
+
+/// Keyboard status register: bit 15 is set when a key is waiting in KBDR
+pub const MR_KBSR: u16 = 0xFE00;
+/// Keyboard data register: the last key pressed, in the low 8 bits
+pub const MR_KBDR: u16 = 0xFE02;
+/// Display status register: bit 15 is set when DDR can take a character.
+/// Console output is synchronous, so the display is always ready.
+pub const MR_DSR: u16 = 0xFE04;
+/// Display data register: the low 8 bits of a write are sent to the console
+pub const MR_DDR: u16 = 0xFE06;
+/// Processor status register. The word here mirrors [`Registers::get_psr`]; the
+/// [`Vm`](crate::Vm) keeps the two in sync around every instruction.
+///
+/// [`Registers::get_psr`]: crate::register::Registers::get_psr
+pub const MR_PSR: u16 = 0xFFFC;
+/// Machine control register: clearing bit 15 stops the clock
+pub const MR_MCR: u16 = 0xFFFE;
+
+const KBSR_READY: u16 = 1 << 15;
+const KBSR_INTERRUPT_ENABLE: u16 = 1 << 14;
+const DSR_READY: u16 = 1 << 15;
+const MCR_CLOCK_ENABLE: u16 = 1 << 15;
+
+/// A device's request to interrupt the running program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupt {
+    /// Entry in the interrupt vector table, x00–xFF
+    pub vector: u8,
+    /// Taken only while the processor runs at a lower priority
+    pub priority: u8,
+}
+
+/// Raised while a key is waiting and KBSR's interrupt-enable bit is set
+pub const KEYBOARD_INTERRUPT: Interrupt = Interrupt { vector: 0x80, priority: 4 };
+
+/// Which kind of access a watchpoint fires on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    /// Either a read or a write
+    Access,
+}
+
+/// Watches the inclusive address range `start..=end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    fn matches(&self, addr: u16, kind: WatchKind) -> bool {
+        (self.start..=self.end).contains(&addr) && (self.kind == kind || self.kind == WatchKind::Access)
+    }
+}
+
+/// A watched access. For reads `old` and `new` are both the value read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub address: u16,
+    /// `Read` or `Write`
+    pub kind: WatchKind,
+    pub old: u16,
+    pub new: u16,
+}
+
+/// A store recorded while write logging is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryWrite {
+    pub address: u16,
+    pub old: u16,
+    pub new: u16,
+}
 
 pub struct Memory {
     mem: [u16; 0x10000],
+    console: Box<dyn Console>,
+    watchpoints: Vec<Watchpoint>,
+    watch_hits: Vec<WatchHit>,
+    write_log: Option<Vec<MemoryWrite>>,
+    psr_write: Option<u16>,
+}
 
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Memory {
     pub fn new() -> Self {
-        Self { mem: [0; 0x10000] }
+        let mut mem = [0; 0x10000];
+        mem[MR_DSR as usize] = DSR_READY;
+        mem[MR_MCR as usize] = MCR_CLOCK_ENABLE;
+        Self {
+            mem,
+            console: Box::new(NullConsole),
+            watchpoints: Vec::new(),
+            watch_hits: Vec::new(),
+            write_log: None,
+            psr_write: None,
+        }
+    }
+
+    /// Connects the console that backs the device registers and the I/O traps.
+    /// Until one is set, no input arrives and output is discarded.
+    pub fn set_console(&mut self, console: Box<dyn Console>) {
+        self.console = console;
     }
 
-    pub fn read(&self, addr: u16) -> u16 {
-        if (addr as usize) >= self.mem.len() {
-            return 0;
+    pub fn console(&mut self) -> &mut dyn Console {
+        self.console.as_mut()
+    }
+
+    /// Reads a word, servicing device registers.
+    ///
+    /// Reading KBSR polls the console and latches a waiting key into KBDR;
+    /// reading KBDR hands the key over and clears the ready bit.
+    pub fn read(&mut self, addr: u16) -> u16 {
+        let value = match addr {
+            MR_KBSR => {
+                self.poll_keyboard();
+                self.mem[MR_KBSR as usize]
+            }
+            MR_KBDR => {
+                self.mem[MR_KBSR as usize] &= !KBSR_READY;
+                self.mem[MR_KBDR as usize]
+            }
+            MR_DSR => DSR_READY,
+            _ => self.mem[addr as usize],
+        };
+        self.record_access(addr, WatchKind::Read, value, value);
+        value
+    }
+
+    fn poll_keyboard(&mut self) {
+        if self.mem[MR_KBSR as usize] & KBSR_READY == 0 {
+            if let Some(key) = self.console.poll_char() {
+                self.mem[MR_KBSR as usize] |= KBSR_READY;
+                self.mem[MR_KBDR as usize] = key as u16;
+            }
+        }
+    }
+
+    /// Returns the highest priority interrupt a device is raising. The keyboard
+    /// is only polled while its interrupts are enabled.
+    pub fn pending_interrupt(&mut self) -> Option<Interrupt> {
+        if self.mem[MR_KBSR as usize] & KBSR_INTERRUPT_ENABLE == 0 {
+            return None;
         }
+        self.poll_keyboard();
+        (self.mem[MR_KBSR as usize] & KBSR_READY != 0).then_some(KEYBOARD_INTERRUPT)
+    }
+
+    /// Returns the stored word without triggering any device side effects.
+    pub fn peek(&self, addr: u16) -> u16 {
         self.mem[addr as usize]
     }
 
+    /// Writes a word. Only the interrupt-enable bit of KBSR is writable, KBDR and
+    /// DSR are read-only, and a write to DDR prints its low byte on the console.
     pub fn write(&mut self, addr: u16, val: u16) {
-        if (addr as usize) < self.mem.len() {
-            self.mem[addr as usize] = val;
+        let old = self.mem[addr as usize];
+        match addr {
+            MR_KBSR => {
+                let kbsr = &mut self.mem[MR_KBSR as usize];
+                *kbsr = (*kbsr & !KBSR_INTERRUPT_ENABLE) | (val & KBSR_INTERRUPT_ENABLE);
+            }
+            MR_KBDR | MR_DSR => {}
+            MR_DDR => {
+                self.mem[MR_DDR as usize] = val;
+                // a display has no way to report a failed write
+                let _ = self.console.write_bytes(&[val as u8]);
+                let _ = self.console.flush();
+            }
+            MR_PSR => {
+                self.mem[MR_PSR as usize] = val;
+                self.psr_write = Some(val);
+            }
+            _ => self.mem[addr as usize] = val,
+        }
+        let new = self.mem[addr as usize];
+        self.record_access(addr, WatchKind::Write, old, new);
+        if let Some(log) = &mut self.write_log {
+            log.push(MemoryWrite { address: addr, old, new });
         }
     }
 
-    pub fn load_program(&mut self, path: &str, mut pc: u16) -> Result<()> {
-        let mut file = File::open(path)?;
-        let mut contents = Vec::new();
-        file.read_to_end(&mut contents)?;
-        
-        let origin = if contents.len() >= 2 {
-            u16::from_be_bytes([contents[0], contents[1]])
-        } else {
-            0x3000
-        };
-        
-        let (start_offset, mem_offset) = if origin >= 0x3000 && origin <= 0x9000 {
-            // with header
-            (2, origin as usize)
-        } else {
-            // no header
-            (0, 0x3000 as usize)
-        };
+    /// Whether the MCR clock-enable bit is still set.
+    pub fn clock_enabled(&self) -> bool {
+        self.mem[MR_MCR as usize] & MCR_CLOCK_ENABLE != 0
+    }
 
-        pc = mem_offset
-            .try_into()
-            .unwrap_or(0x3000);
-        
-        // Load instructions
-        for i in (start_offset..contents.len()).step_by(2) {
-            if i + 1 < contents.len() {
-                let instr = u16::from_be_bytes([contents[i], contents[i + 1]]);
-                let offset = mem_offset + (i - start_offset) / 2;
-                if offset < self.mem.len() {
-                    self.mem[offset] = instr;
-                }
-            }
+    /// Updates the PSR word without it counting as a write.
+    pub fn mirror_psr(&mut self, psr: u16) {
+        self.mem[MR_PSR as usize] = psr;
+    }
+
+    /// The last value stored to the PSR since the previous call, if any.
+    pub fn take_psr_write(&mut self) -> Option<u16> {
+        self.psr_write.take()
+    }
+
+    fn record_access(&mut self, address: u16, kind: WatchKind, old: u16, new: u16) {
+        if self.watchpoints.iter().any(|w| w.matches(address, kind)) {
+            self.watch_hits.push(WatchHit { address, kind, old, new });
         }
-        
-        Ok(())
+    }
+
+    /// Adds a watchpoint. Returns `false` if an identical one is already set.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        if self.watchpoints.contains(&watchpoint) {
+            return false;
+        }
+        self.watchpoints.push(watchpoint);
+        true
+    }
+
+    /// Removes a watchpoint. Returns `false` if it was not set.
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        let before = self.watchpoints.len();
+        self.watchpoints.retain(|w| w != watchpoint);
+        before != self.watchpoints.len()
+    }
+
+    /// Removes every watchpoint whose range contains `addr`, returning how many.
+    pub fn remove_watchpoints_at(&mut self, addr: u16) -> usize {
+        let before = self.watchpoints.len();
+        self.watchpoints.retain(|w| !(w.start..=w.end).contains(&addr));
+        before - self.watchpoints.len()
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Watched accesses recorded since the last [`Memory::take_watch_hits`].
+    pub fn watch_hits(&self) -> &[WatchHit] {
+        &self.watch_hits
+    }
+
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.watch_hits)
+    }
+
+    /// Turns recording of every [`Memory::write`] on or off. Turning it off drops
+    /// anything not yet taken.
+    pub fn set_write_logging(&mut self, enabled: bool) {
+        self.write_log = enabled.then(Vec::new);
+    }
+
+    /// Writes recorded since the last call, oldest first.
+    pub fn take_writes(&mut self) -> Vec<MemoryWrite> {
+        self.write_log.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Blocks for the next key for the input traps, taking a key already
+    /// latched in KBDR first. Returns `None` if no key can arrive.
+    pub fn read_key(&mut self) -> Option<u8> {
+        if self.mem[MR_KBSR as usize] & KBSR_READY != 0 {
+            return Some(self.read(MR_KBDR) as u8);
+        }
+        self.console.read_char()
+    }
+
+    /// Stores `words` starting at `origin` without any device side effects.
+    pub fn load_words(&mut self, origin: u16, words: &[u16]) {
+        let start = origin as usize;
+        let end = (start + words.len()).min(self.mem.len());
+        self.mem[start..end].copy_from_slice(&words[..end - start]);
     }
 }
 
@@ -66,38 +282,75 @@ impl Memory {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::console::BufferConsole;
+
     #[test]
-    fn test_load_program_with_header() {
+    fn test_keyboard_registers() {
         let mut mem = Memory::new();
-        let pc = 0x3000;
+        assert_eq!(mem.read(MR_KBSR), 0);
 
-        let binary: Vec<u8> = vec![
-            0x40, 0x00, // origin = 0x4000
-            0x12, 0x34, // instruction 1
-            0xAB, 0xCD  // instruction 2
-        ];
+        mem.set_console(Box::new(BufferConsole::with_input(b"wa")));
+        assert_eq!(mem.read(MR_KBSR), 0x8000);
+        // key stays latched until KBDR is read
+        assert_eq!(mem.read(MR_KBSR), 0x8000);
+        assert_eq!(mem.read(MR_KBDR), b'w' as u16);
+        assert_eq!(mem.read_key(), Some(b'a'));
+        assert_eq!(mem.read(MR_KBSR), 0);
+    }
 
-        std::fs::write("test_prog_with_header.obj", &binary).unwrap();
-        mem.load_program("test_prog_with_header.obj", pc).unwrap();
+    #[test]
+    fn test_keyboard_interrupt() {
+        let mut mem = Memory::new();
+        mem.set_console(Box::new(BufferConsole::with_input(b"k")));
+        assert_eq!(mem.pending_interrupt(), None); // not enabled, so the key stays unread
 
-        assert_eq!(mem.read(0x4000), 0x1234);
-        assert_eq!(mem.read(0x4001), 0xABCD);
+        mem.write(MR_KBSR, KBSR_INTERRUPT_ENABLE);
+        assert_eq!(mem.pending_interrupt(), Some(KEYBOARD_INTERRUPT));
+        assert_eq!(mem.read(MR_KBDR), b'k' as u16);
+        assert_eq!(mem.pending_interrupt(), None);
     }
 
     #[test]
-    fn test_load_program_without_header() {
+    fn test_watchpoints() {
         let mut mem = Memory::new();
-        let pc = 0x3000;
+        mem.write(0x4001, 7);
+        mem.add_watchpoint(Watchpoint { start: 0x4000, end: 0x4001, kind: WatchKind::Write });
+        mem.add_watchpoint(Watchpoint { start: 0x5000, end: 0x5000, kind: WatchKind::Access });
+
+        mem.read(0x4001);
+        mem.write(0x4001, 9);
+        mem.read(0x5000);
+        mem.write(0x4002, 1);
+        assert_eq!(mem.take_watch_hits(), vec![
+            WatchHit { address: 0x4001, kind: WatchKind::Write, old: 7, new: 9 },
+            WatchHit { address: 0x5000, kind: WatchKind::Read, old: 0, new: 0 },
+        ]);
+
+        assert_eq!(mem.remove_watchpoints_at(0x4000), 1);
+        mem.write(0x4001, 10);
+        assert!(mem.watch_hits().is_empty());
+    }
 
-        let binary: Vec<u8> = vec![
-            0x12, 0x34,
-            0x56, 0x78
-        ];
+    #[test]
+    fn test_display_registers() {
+        let console = BufferConsole::new();
+        let mut mem = Memory::new();
+        mem.set_console(Box::new(console.clone()));
 
-        std::fs::write("test_prog_no_header.obj", &binary).unwrap();
-        mem.load_program("test_prog_no_header.obj", pc).unwrap();
+        assert_eq!(mem.read(MR_DSR), 0x8000);
+        mem.write(MR_DDR, b'h' as u16);
+        mem.write(MR_DDR, 0x0169); // only the low byte is printed
+        mem.write(MR_DSR, 0);
+        assert_eq!(console.output(), b"hi");
+        assert_eq!(mem.read(MR_DSR), 0x8000);
+    }
 
-        assert_eq!(mem.read(0x3000), 0x1234);
-        assert_eq!(mem.read(0x3001), 0x5678);
+    #[test]
+    fn test_keyboard_register_writes() {
+        let mut mem = Memory::new();
+        mem.write(MR_KBSR, 0xFFFF);
+        mem.write(MR_KBDR, 0x1234);
+        assert_eq!(mem.read(MR_KBSR), 0x4000);
+        assert_eq!(mem.read(MR_KBDR), 0);
     }
 }
@@ -1,4 +1,4 @@
-use crate::{register::Registers, utils, Memory};
+use crate::{memory::Memory, register::Registers, utils};
 
 #[derive(Debug)]
 #[repr(u16)]
@@ -79,11 +79,11 @@ pub fn handle_and(instr: u16, reg: &mut Registers) {
 
     if imm_flag == 1 {  // Immediate mode
         let imm5 = utils::sign_extend(instr & 0x1F, 5);
-        reg.set(dr as usize, reg.get(sr1 as usize) & imm5);
+        reg.set(dr, reg.get(sr1) & imm5);
     }
     else {  // Register mode
         let sr2 = instr & 0x7;
-        reg.set(dr as usize, reg.get(sr1 as usize) & reg.get(sr2 as usize));
+        reg.set(dr, reg.get(sr1) & reg.get(sr2 as usize));
     }
 }
 
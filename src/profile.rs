@@ -0,0 +1,230 @@
+//! Instruction-level profiling.
+//!
+//! A [`Profiler`] attached to a [`Vm`](crate::Vm) counts how often each address
+//! and each opcode executes, and attributes every instruction to the subroutine
+//! it ran in. Subroutines are tracked through the R7 linkage: JSR, JSRR and a TRAP
+//! that jumps into an operating system enter one, and a RET to the matching
+//! return address leaves it. Interrupt handlers are counted as part of whatever
+//! routine they interrupted.
+//!
+//! [`Profiler::report`] gives a text report sorted by hotness, and
+//! [`Profiler::folded`] the call stacks in the folded format read by flame graph
+//! tools such as `flamegraph.pl` and `inferno-flamegraph`:
+//!
+//! ```text
+//! MAIN;MULTIPLY 1830
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+
+use crate::disasm::disassemble_with_symbols;
+use crate::opcode::OpCode;
+use crate::symbols::SymbolTable;
+
+/// Calls nested deeper than this are counted in their caller
+const MAX_DEPTH: usize = 256;
+
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    total: u64,
+    /// Executions and the last instruction word seen at each address
+    addresses: BTreeMap<u16, (u64, u16)>,
+    opcodes: [u64; 16],
+    calls: BTreeMap<u16, u64>,
+    /// Entry addresses of the routines currently running, outermost first
+    stack: Vec<u16>,
+    /// Where the RET leaving each routine in `stack` lands; `None` for the outermost
+    return_addresses: Vec<Option<u16>>,
+    /// Instructions executed with exactly this stack of subroutine entries
+    stacks: HashMap<Vec<u16>, u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts the instruction `instr` at `pc`, which left the PC at `next_pc`.
+    pub fn record(&mut self, pc: u16, instr: u16, next_pc: u16) {
+        if self.stack.is_empty() {
+            self.stack.push(pc);
+            self.return_addresses.push(None);
+        }
+        self.total += 1;
+        let address = self.addresses.entry(pc).or_default();
+        address.0 += 1;
+        address.1 = instr;
+        self.opcodes[(instr >> 12) as usize] += 1;
+
+        match self.stacks.get_mut(self.stack.as_slice()) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.stack.clone(), 1);
+            }
+        }
+
+        let return_address = pc.wrapping_add(1);
+        match OpCode::from_instr(instr) {
+            OpCode::JSR => self.call(next_pc, return_address),
+            OpCode::TRAP if next_pc != return_address => self.call(next_pc, return_address),
+            OpCode::JMP if (instr >> 6) & 0x7 == 7 => {
+                // RET: unwind to the frame it returns from, if there is one
+                if let Some(depth) = self.return_addresses.iter().rposition(|&ret| ret == Some(next_pc)) {
+                    self.stack.truncate(depth);
+                    self.return_addresses.truncate(depth);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn call(&mut self, entry: u16, return_address: u16) {
+        *self.calls.entry(entry).or_default() += 1;
+        if self.stack.len() < MAX_DEPTH {
+            self.stack.push(entry);
+            self.return_addresses.push(Some(return_address));
+        }
+    }
+
+    /// How many instructions have been counted.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// How many times the instruction at `address` executed.
+    pub fn count_at(&self, address: u16) -> u64 {
+        self.addresses.get(&address).map_or(0, |&(count, _)| count)
+    }
+
+    /// How many instructions with this opcode executed.
+    pub fn opcode_count(&self, opcode: OpCode) -> u64 {
+        self.opcodes[opcode as usize]
+    }
+
+    /// Per subroutine entry: `(calls, self, total)` where `self` counts the
+    /// instructions run in the routine itself and `total` adds those of the
+    /// routines it called.
+    pub fn subroutines(&self) -> BTreeMap<u16, (u64, u64, u64)> {
+        let mut subroutines: BTreeMap<u16, (u64, u64, u64)> = BTreeMap::new();
+        for (stack, &count) in &self.stacks {
+            for (i, &entry) in stack.iter().enumerate() {
+                let stats = subroutines.entry(entry).or_default();
+                // a recursive routine is only counted once per instruction
+                if !stack[..i].contains(&entry) {
+                    stats.2 += count;
+                }
+                if i == stack.len() - 1 {
+                    stats.1 += count;
+                }
+            }
+        }
+        for (entry, stats) in &mut subroutines {
+            stats.0 = self.calls.get(entry).copied().unwrap_or(0);
+        }
+        subroutines
+    }
+
+    /// A report of the hottest addresses, opcodes and subroutines, labelled from `symbols`.
+    pub fn report(&self, symbols: &SymbolTable) -> String {
+        let percent = |count: u64| count as f64 * 100.0 / self.total.max(1) as f64;
+        let mut out = format!("Instructions executed: {}\n", self.total);
+
+        let mut subroutines: Vec<_> = self.subroutines().into_iter().collect();
+        subroutines.sort_by_key(|&(entry, (_, own, total))| (std::cmp::Reverse((total, own)), entry));
+        out.push_str("\nSubroutines:\n     total       %      self       %     calls  routine\n");
+        for (entry, (calls, own, total)) in subroutines {
+            let _ = writeln!(
+                out,
+                "{:>10}  {:>5.1}%  {:>8}  {:>5.1}%  {:>8}  {}",
+                total,
+                percent(total),
+                own,
+                percent(own),
+                calls,
+                frame_name(entry, symbols)
+            );
+        }
+
+        let mut opcodes: Vec<_> = (0..16u16).filter(|&op| self.opcodes[op as usize] > 0).collect();
+        opcodes.sort_by_key(|&op| (std::cmp::Reverse(self.opcodes[op as usize]), op));
+        out.push_str("\nOpcodes:\n     count       %  opcode\n");
+        for op in opcodes {
+            let count = self.opcodes[op as usize];
+            let _ = writeln!(out, "{:>10}  {:>5.1}%  {:?}", count, percent(count), OpCode::from_instr(op << 12));
+        }
+
+        let mut addresses: Vec<_> = self.addresses.iter().collect();
+        addresses.sort_by_key(|&(&address, &(count, _))| (std::cmp::Reverse(count), address));
+        out.push_str("\nAddresses:\n     count       %  address  instruction\n");
+        for (&address, &(count, instr)) in addresses {
+            let label = match symbols.nearest(address) {
+                Some((label, 0)) => format!("  ; {}", label),
+                Some((label, offset)) => format!("  ; {}+{}", label, offset),
+                None => String::new(),
+            };
+            let _ = writeln!(
+                out,
+                "{:>10}  {:>5.1}%  x{:04X}    {:04X}  {}{}",
+                count,
+                percent(count),
+                address,
+                instr,
+                disassemble_with_symbols(instr, address, symbols),
+                label
+            );
+        }
+        out
+    }
+
+    /// One `outer;inner count` line per distinct call stack, sorted.
+    pub fn folded(&self, symbols: &SymbolTable) -> String {
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, count)| {
+                let names: Vec<String> = stack.iter().map(|&entry| frame_name(entry, symbols)).collect();
+                format!("{} {}\n", names.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+        lines.concat()
+    }
+}
+
+/// The label at a subroutine's entry, or its address.
+fn frame_name(entry: u16, symbols: &SymbolTable) -> String {
+    symbols.label_at(entry).map_or_else(|| format!("x{:04X}", entry), str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_call_stacks() {
+        let mut profiler = Profiler::new();
+        profiler.record(0x3000, 0x4802, 0x3003); // JSR x3003
+        profiler.record(0x3003, 0x1021, 0x3004); // ADD R0, R0, #1
+        profiler.record(0x3004, 0xC1C0, 0x3001); // RET
+        profiler.record(0x3001, 0xF025, 0x3002); // HALT
+
+        assert_eq!(profiler.total(), 4);
+        assert_eq!(profiler.opcode_count(OpCode::JSR), 1);
+        let subroutines = profiler.subroutines();
+        assert_eq!(subroutines[&0x3000], (0, 2, 4));
+        assert_eq!(subroutines[&0x3003], (1, 2, 2));
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("MAIN", 0x3000);
+        assert_eq!(profiler.folded(&symbols), "MAIN 2\nMAIN;x3003 2\n");
+    }
+
+    #[test]
+    fn test_unmatched_ret_is_a_jump() {
+        let mut profiler = Profiler::new();
+        profiler.record(0x3000, 0xC1C0, 0x4000); // RET without a call
+        profiler.record(0x4000, 0xF025, 0x4001);
+        assert_eq!(profiler.folded(&SymbolTable::new()), "x3000 2\n");
+    }
+}
@@ -1,7 +1,23 @@
+/// Bit 15 of the PSR: set while running in user mode
+const PSR_USER: u16 = 1 << 15;
+
+/// Supervisor stack pointer used until an OS or program sets its own
+pub const INITIAL_SSP: u16 = 0x3000;
+
 pub struct Registers {
     regs: [u16; 8],
     pc: u16,
     cond: ConditionFlag,
+    privilege: Privilege,
+    priority: u8,
+    saved_ssp: u16,
+    saved_usp: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Privilege {
+    Supervisor,
+    User,
 }
 
 #[derive(Clone, Copy)]
@@ -11,12 +27,22 @@ pub enum ConditionFlag {
     NEG = 4,
 }
 
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Registers {
     pub fn new() -> Self {
         Self {
             regs: [0; 8],
             pc: 0,
             cond: ConditionFlag::ZRO,
+            privilege: Privilege::User,
+            priority: 0,
+            saved_ssp: INITIAL_SSP,
+            saved_usp: 0,
         }
     }
 
@@ -58,6 +84,82 @@ impl Registers {
             ConditionFlag::NEG => 4,
         }
     }
+
+    /// Sets the condition flag from its N/Z/P bit encoding (4, 2 or 1).
+    /// If several bits are set, N wins over Z, and Z over P.
+    pub fn set_cond_flag(&mut self, flag: u16) {
+        self.cond = if flag & 4 != 0 {
+            ConditionFlag::NEG
+        } else if flag & 2 != 0 {
+            ConditionFlag::ZRO
+        } else {
+            ConditionFlag::POS
+        };
+    }
+
+    pub fn get_privilege(&self) -> Privilege {
+        self.privilege
+    }
+
+    pub fn set_privilege(&mut self, privilege: Privilege) {
+        self.privilege = privilege;
+    }
+
+    /// Priority level, 0 (lowest) to 7
+    pub fn get_priority(&self) -> u8 {
+        self.priority
+    }
+
+    pub fn set_priority(&mut self, priority: u8) {
+        self.priority = priority & 0x7;
+    }
+
+    /// Processor Status Register: privilege in bit 15, priority in bits 10–8 and
+    /// the N/Z/P condition codes in bits 2–0.
+    pub fn get_psr(&self) -> u16 {
+        let user = if self.privilege == Privilege::User { PSR_USER } else { 0 };
+        user | (self.priority as u16) << 8 | self.get_cond_flag()
+    }
+
+    /// Sets privilege, priority and condition codes at once. This does not swap
+    /// stack pointers; see [`Registers::switch_to_supervisor_stack`].
+    pub fn set_psr(&mut self, psr: u16) {
+        self.privilege = if psr & PSR_USER != 0 { Privilege::User } else { Privilege::Supervisor };
+        self.set_priority((psr >> 8) as u8);
+        self.set_cond_flag(psr & 0x7);
+    }
+
+    /// Supervisor stack pointer, saved here while R6 holds the user one
+    pub fn get_saved_ssp(&self) -> u16 {
+        self.saved_ssp
+    }
+
+    pub fn set_saved_ssp(&mut self, val: u16) {
+        self.saved_ssp = val;
+    }
+
+    /// User stack pointer, saved here while R6 holds the supervisor one
+    pub fn get_saved_usp(&self) -> u16 {
+        self.saved_usp
+    }
+
+    pub fn set_saved_usp(&mut self, val: u16) {
+        self.saved_usp = val;
+    }
+
+    /// Saves R6 to Saved_USP and loads R6 from Saved_SSP. Condition codes are
+    /// left alone.
+    pub fn switch_to_supervisor_stack(&mut self) {
+        self.saved_usp = self.regs[6];
+        self.regs[6] = self.saved_ssp;
+    }
+
+    /// Saves R6 to Saved_SSP and loads R6 from Saved_USP. Condition codes are
+    /// left alone.
+    pub fn switch_to_user_stack(&mut self) {
+        self.saved_ssp = self.regs[6];
+        self.regs[6] = self.saved_usp;
+    }
 }
 
 
@@ -80,6 +182,32 @@ mod tests {
         assert_eq!(regs.get_cond_flag(), 1);
     }
 
+    #[test]
+    fn test_psr() {
+        let mut regs = Registers::new();
+        assert_eq!(regs.get_psr(), 0x8002); // user mode, priority 0, Z
+
+        regs.set_psr(0x0401);
+        assert_eq!(regs.get_privilege(), Privilege::Supervisor);
+        assert_eq!(regs.get_priority(), 4);
+        assert_eq!(regs.get_cond_flag(), 1);
+        assert_eq!(regs.get_psr(), 0x0401);
+    }
+
+    #[test]
+    fn test_stack_switch() {
+        let mut regs = Registers::new();
+        regs.set(6, 0xFE00);
+        regs.switch_to_supervisor_stack();
+        assert_eq!(regs.get(6), INITIAL_SSP);
+        assert_eq!(regs.get_saved_usp(), 0xFE00);
+
+        regs.set(6, 0x2FFE);
+        regs.switch_to_user_stack();
+        assert_eq!(regs.get(6), 0xFE00);
+        assert_eq!(regs.get_saved_ssp(), 0x2FFE);
+    }
+
     #[test]
     fn test_program_counter_set_get() {
         let mut regs = Registers::new();
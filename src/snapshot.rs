@@ -0,0 +1,189 @@
+//! Machine snapshots.
+//!
+//! A [`Snapshot`] is the complete state of a [`Vm`](crate::Vm): every memory word
+//! (device registers included), R0–R7, PC, PSR, the saved stack pointers and
+//! whether TRAP goes through an OS. [`Vm::snapshot`](crate::Vm::snapshot) takes
+//! one and [`Vm::restore`](crate::Vm::restore) puts it back.
+//!
+//! On disk a snapshot is a fixed-size big-endian file:
+//!
+//! | words   | contents                                    |
+//! |---------|---------------------------------------------|
+//! | 4       | magic `LC3SNAP\0`                           |
+//! | 1       | format version, currently [`VERSION`]       |
+//! | 1       | flags: bit 0 set when TRAP goes through an OS |
+//! | 8       | R0–R7                                       |
+//! | 4       | PC, PSR, Saved_SSP, Saved_USP               |
+//! | 65536   | memory x0000–xFFFF                          |
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"LC3SNAP\0";
+/// Version written by [`Snapshot::to_bytes`]
+pub const VERSION: u16 = 1;
+const FLAG_OS_TRAPS: u16 = 1;
+const HEADER_WORDS: usize = 4 + 1 + 1 + 8 + 4;
+const FILE_LEN: usize = (HEADER_WORDS + 0x10000) * 2;
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub registers: [u16; 8],
+    pub pc: u16,
+    pub psr: u16,
+    pub saved_ssp: u16,
+    pub saved_usp: u16,
+    pub os_traps: bool,
+    /// All 65536 words
+    pub memory: Vec<u16>,
+}
+
+impl fmt::Debug for Snapshot {
+    // the memory would drown everything else out
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("registers", &self.registers)
+            .field("pc", &self.pc)
+            .field("psr", &self.psr)
+            .field("saved_ssp", &self.saved_ssp)
+            .field("saved_usp", &self.saved_usp)
+            .field("os_traps", &self.os_traps)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Why a snapshot could not be read or written.
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io { name: String, error: io::Error },
+    /// The file does not start with the snapshot magic
+    NotASnapshot { name: String },
+    UnsupportedVersion { name: String, version: u16 },
+    /// The file is not the size every snapshot has
+    WrongLength { name: String, len: usize },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { name, error } => write!(f, "{}: {}", name, error),
+            Self::NotASnapshot { name } => write!(f, "{}: not an LC-3 snapshot", name),
+            Self::UnsupportedVersion { name, version } => {
+                write!(f, "{}: snapshot version {} is not supported (expected {})", name, version, VERSION)
+            }
+            Self::WrongLength { name, len } => {
+                write!(f, "{}: snapshot is {} bytes long, expected {}", name, len, FILE_LEN)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let flags = if self.os_traps { FLAG_OS_TRAPS } else { 0 };
+        let header = [VERSION, flags]
+            .into_iter()
+            .chain(self.registers)
+            .chain([self.pc, self.psr, self.saved_ssp, self.saved_usp]);
+        let mut bytes = Vec::with_capacity(FILE_LEN);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend(header.chain(self.memory.iter().copied()).flat_map(u16::to_be_bytes));
+        bytes
+    }
+
+    /// Decodes a snapshot file's contents; `name` is used in error messages.
+    pub fn from_bytes(name: &str, bytes: &[u8]) -> Result<Self, SnapshotError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(SnapshotError::NotASnapshot { name: name.to_string() });
+        }
+        let words: Vec<u16> = bytes[MAGIC.len()..]
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        // check the version first so a future layout gets the clearer error
+        if let Some(&version) = words.first().filter(|&&v| v != VERSION) {
+            return Err(SnapshotError::UnsupportedVersion { name: name.to_string(), version });
+        }
+        if bytes.len() != FILE_LEN {
+            return Err(SnapshotError::WrongLength { name: name.to_string(), len: bytes.len() });
+        }
+        let mut registers = [0; 8];
+        registers.copy_from_slice(&words[2..10]);
+        Ok(Self {
+            registers,
+            pc: words[10],
+            psr: words[11],
+            saved_ssp: words[12],
+            saved_usp: words[13],
+            os_traps: words[1] & FLAG_OS_TRAPS != 0,
+            memory: words[HEADER_WORDS - 4..].to_vec(),
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let path = path.as_ref();
+        fs::write(path, self.to_bytes())
+            .map_err(|error| SnapshotError::Io { name: path.display().to_string(), error })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        let name = path.as_ref().display().to_string();
+        let bytes = fs::read(path).map_err(|error| SnapshotError::Io { name: name.clone(), error })?;
+        Self::from_bytes(&name, &bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Snapshot {
+        let mut memory = vec![0; 0x10000];
+        memory[0x3000] = 0xF025;
+        memory[0xFFFE] = 0x8000;
+        Snapshot {
+            registers: [1, 2, 3, 4, 5, 6, 7, 0xFFFF],
+            pc: 0x3001,
+            psr: 0x8004,
+            saved_ssp: 0x3000,
+            saved_usp: 0xFE00,
+            os_traps: true,
+            memory,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let snapshot = sample();
+        let bytes = snapshot.to_bytes();
+        assert_eq!(bytes.len(), FILE_LEN);
+        assert_eq!(&bytes[8..12], [0, 1, 0, 1]);
+        assert_eq!(Snapshot::from_bytes("snap", &bytes).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let bytes = sample().to_bytes();
+        assert!(matches!(Snapshot::from_bytes("snap", b"3000"), Err(SnapshotError::NotASnapshot { .. })));
+        assert!(matches!(
+            Snapshot::from_bytes("snap", &bytes[..100]),
+            Err(SnapshotError::WrongLength { len: 100, .. })
+        ));
+
+        let mut future = bytes.clone();
+        future[9] = 2;
+        let err = Snapshot::from_bytes("snap", &future).unwrap_err();
+        assert_eq!(err.to_string(), "snap: snapshot version 2 is not supported (expected 1)");
+    }
+}
@@ -0,0 +1,239 @@
+//! Symbol tables and source line maps.
+//!
+//! The assembler produces both alongside an object file: a `.sym` file in the
+//! `lc3as`/lc3tools layout mapping labels to addresses, and a `.dbg` line map
+//! mapping addresses to `file:line`. [`DebugInfo`] reads them back so addresses can
+//! be printed as `LOOP+3 (main.asm:42)` instead of raw hex.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+/// Labels and the addresses they name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    by_name: BTreeMap<String, u16>,
+    by_address: BTreeMap<u16, String>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a label. When several labels share an address, the first one added is
+    /// used to describe that address.
+    pub fn insert(&mut self, name: &str, address: u16) {
+        self.by_name.insert(name.to_string(), address);
+        self.by_address.entry(address).or_insert_with(|| name.to_string());
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).copied()
+    }
+
+    /// Returns the label exactly at `address`.
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.by_address.get(&address).map(String::as_str)
+    }
+
+    /// Returns the closest label at or below `address` and the distance from it.
+    pub fn nearest(&self, address: u16) -> Option<(&str, u16)> {
+        self.by_address
+            .range(..=address)
+            .next_back()
+            .map(|(base, name)| (name.as_str(), address - base))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    /// Iterates over all labels in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+        let mut symbols: Vec<_> = self.by_name.iter().map(|(name, addr)| (name.as_str(), *addr)).collect();
+        symbols.sort_by_key(|&(name, addr)| (addr, name));
+        symbols.into_iter()
+    }
+
+    /// Formats the table as an `lc3as`-style `.sym` file.
+    pub fn to_sym(&self) -> String {
+        let mut out = String::from("// Symbol table\n// Scope level 0:\n");
+        out.push_str("//\tSymbol Name       Page Address\n");
+        out.push_str("//\t----------------  ------------\n");
+        for (name, address) in self.iter() {
+            let _ = writeln!(out, "//\t{:<16}  {:04X}", name, address);
+        }
+        out
+    }
+
+    /// Parses a `.sym` file. Comment markers and header lines are skipped, so both
+    /// `lc3as` output and plain `LABEL x3000` lines are accepted.
+    pub fn parse_sym(text: &str) -> Result<Self> {
+        let mut table = Self::new();
+        for (i, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.trim_start_matches('/').split_whitespace().collect();
+            let [name, address] = fields[..] else {
+                continue;
+            };
+            if name == "Symbol" || name.starts_with('-') {
+                continue;
+            }
+            let digits = address.trim_start_matches(['x', 'X']);
+            let address = u16::from_str_radix(digits, 16).map_err(|_| {
+                Error::new(ErrorKind::InvalidData, format!("line {}: invalid address '{}'", i + 1, address))
+            })?;
+            table.insert(name, address);
+        }
+        Ok(table)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::parse_sym(&fs::read_to_string(path)?)
+    }
+}
+
+/// Where the word at an address came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+}
+
+/// Maps addresses back to the source lines that produced them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineMap {
+    lines: BTreeMap<u16, SourceLocation>,
+}
+
+impl LineMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, address: u16, file: &str, line: usize) {
+        self.lines.insert(address, SourceLocation { file: file.to_string(), line });
+    }
+
+    pub fn get(&self, address: u16) -> Option<&SourceLocation> {
+        self.lines.get(&address)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &SourceLocation)> {
+        self.lines.iter().map(|(addr, loc)| (*addr, loc))
+    }
+
+    /// Formats the map as a `.dbg` file with one `xADDR file:line` entry per line.
+    pub fn to_dbg(&self) -> String {
+        let mut out = String::from("// Line map\n");
+        for (address, loc) in &self.lines {
+            let _ = writeln!(out, "x{:04X} {}:{}", address, loc.file, loc.line);
+        }
+        out
+    }
+
+    pub fn parse_dbg(text: &str) -> Result<Self> {
+        let mut map = Self::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let invalid = || Error::new(ErrorKind::InvalidData, format!("line {}: invalid entry '{}'", i + 1, line));
+            let (address, location) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let (file, number) = location.trim().rsplit_once(':').ok_or_else(invalid)?;
+            let address = u16::from_str_radix(address.trim_start_matches(['x', 'X']), 16).map_err(|_| invalid())?;
+            let number = number.parse().map_err(|_| invalid())?;
+            map.insert(address, file, number);
+        }
+        Ok(map)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::parse_dbg(&fs::read_to_string(path)?)
+    }
+}
+
+/// Symbols and line map for a loaded program.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    pub symbols: SymbolTable,
+    pub lines: LineMap,
+}
+
+impl DebugInfo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the `.sym` and `.dbg` files next to an object file, if they exist.
+    pub fn load_for(obj_path: impl AsRef<Path>) -> Result<Self> {
+        let mut info = Self::new();
+        info.merge_for(obj_path)?;
+        Ok(info)
+    }
+
+    /// Adds the `.sym` and `.dbg` files next to an object file, if they exist.
+    pub fn merge_for(&mut self, obj_path: impl AsRef<Path>) -> Result<()> {
+        let sym_path = obj_path.as_ref().with_extension("sym");
+        if sym_path.exists() {
+            for (name, address) in SymbolTable::load(sym_path)?.iter() {
+                self.symbols.insert(name, address);
+            }
+        }
+        let dbg_path = obj_path.as_ref().with_extension("dbg");
+        if dbg_path.exists() {
+            for (address, loc) in LineMap::load(dbg_path)?.iter() {
+                self.lines.insert(address, &loc.file, loc.line);
+            }
+        }
+        Ok(())
+    }
+
+    /// Describes an address as `LABEL+offset (file:line)`, falling back to hex for
+    /// whatever is unknown.
+    pub fn describe(&self, address: u16) -> String {
+        let mut out = match self.symbols.nearest(address) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{}+{}", name, offset),
+            None => format!("x{:04X}", address),
+        };
+        if let Some(loc) = self.lines.get(address) {
+            let _ = write!(out, " ({}:{})", loc.file, loc.line);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sym_round_trip() {
+        let mut table = SymbolTable::new();
+        table.insert("START", 0x3000);
+        table.insert("LOOP", 0x3002);
+
+        let parsed = SymbolTable::parse_sym(&table.to_sym()).unwrap();
+        assert_eq!(parsed, table);
+        assert_eq!(parsed.address_of("LOOP"), Some(0x3002));
+    }
+
+    #[test]
+    fn test_describe() {
+        let mut info = DebugInfo::new();
+        info.symbols.insert("LOOP", 0x3002);
+        info.lines = LineMap::parse_dbg("// Line map\nx3005 main.asm:42\n").unwrap();
+
+        assert_eq!(info.describe(0x3002), "LOOP");
+        assert_eq!(info.describe(0x3005), "LOOP+3 (main.asm:42)");
+        assert_eq!(info.describe(0x2FFF), "x2FFF");
+    }
+}
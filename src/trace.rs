@@ -0,0 +1,232 @@
+//! Instruction execution traces.
+//!
+//! A [`Tracer`] attached to a [`Vm`](crate::Vm) receives one [`TraceRecord`] per
+//! executed instruction and writes it either as an aligned text line for reading or
+//! as a JSON object per line for diffing against other simulators. Given the
+//! program's [`DebugInfo`], operands show labels and each PC its label and source
+//! line:
+//!
+//! ```text
+//! x3001  3002  ST R0, COUNT             CC=P  [x3004] x0000 -> x0005  ; MAIN+1 (main.asm:4)
+//! {"pc":12289,"instr":12290,"asm":"ST R0, COUNT","location":"MAIN+1 (main.asm:4)","regs":{},"cc":"P","writes":[{"addr":12292,"old":0,"new":5}]}
+//! ```
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::disasm::disassemble_with_symbols;
+use crate::memory::MemoryWrite;
+use crate::symbols::DebugInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    /// JSON Lines
+    Json,
+}
+
+impl TraceFormat {
+    /// `.jsonl` and `.json` files get [`TraceFormat::Json`], anything else text.
+    pub fn for_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl" | "json") => Self::Json,
+            _ => Self::Text,
+        }
+    }
+}
+
+/// What one instruction did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub pc: u16,
+    pub instr: u16,
+    /// General purpose registers whose value changed, as `(index, new value)`
+    pub registers: Vec<(usize, u16)>,
+    /// Condition flag after the instruction: 4, 2 or 1 for N, Z or P
+    pub cond: u16,
+    pub writes: Vec<MemoryWrite>,
+}
+
+impl TraceRecord {
+    fn cond_name(&self) -> &'static str {
+        match self.cond {
+            4 => "N",
+            2 => "Z",
+            _ => "P",
+        }
+    }
+
+    /// Where the instruction is in the program, if `info` knows anything about it.
+    fn location(&self, info: &DebugInfo) -> Option<String> {
+        let known = info.symbols.nearest(self.pc).is_some() || info.lines.get(self.pc).is_some();
+        known.then(|| info.describe(self.pc))
+    }
+
+    pub fn to_text(&self, info: &DebugInfo) -> String {
+        let asm = disassemble_with_symbols(self.instr, self.pc, &info.symbols);
+        let mut out = format!("x{:04X}  {:04X}  {:<24}", self.pc, self.instr, asm);
+        for (r, value) in &self.registers {
+            let _ = write!(out, " R{}=x{:04X}", r, value);
+        }
+        let _ = write!(out, " CC={}", self.cond_name());
+        for write in &self.writes {
+            let _ = write!(out, "  [x{:04X}] x{:04X} -> x{:04X}", write.address, write.old, write.new);
+        }
+        if let Some(location) = self.location(info) {
+            let _ = write!(out, "  ; {}", location);
+        }
+        out
+    }
+
+    pub fn to_json(&self, info: &DebugInfo) -> String {
+        let asm = disassemble_with_symbols(self.instr, self.pc, &info.symbols);
+        let mut out = format!("{{\"pc\":{},\"instr\":{},\"asm\":{}", self.pc, self.instr, json_string(&asm));
+        if let Some(location) = self.location(info) {
+            let _ = write!(out, ",\"location\":{}", json_string(&location));
+        }
+        out.push_str(",\"regs\":{");
+        for (i, (r, value)) in self.registers.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            let _ = write!(out, "{}\"R{}\":{}", separator, r, value);
+        }
+        let _ = write!(out, "}},\"cc\":\"{}\",\"writes\":[", self.cond_name());
+        for (i, write) in self.writes.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            let _ = write!(
+                out,
+                "{}{{\"addr\":{},\"old\":{},\"new\":{}}}",
+                separator, write.address, write.old, write.new
+            );
+        }
+        out.push_str("]}");
+        out
+    }
+}
+
+/// `text` as a JSON string literal.
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Writes trace records to a file or any other writer.
+///
+/// Write errors do not interrupt the program being traced: the first one is kept,
+/// later records are dropped, and [`Tracer::finish`] reports it.
+pub struct Tracer {
+    out: Box<dyn Write>,
+    format: TraceFormat,
+    info: DebugInfo,
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new<W: Write + 'static>(out: W, format: TraceFormat) -> Self {
+        Self { out: Box::new(out), format, info: DebugInfo::new(), error: None }
+    }
+
+    /// Creates `path`, picking the format from its extension.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::create(path.as_ref())?;
+        Ok(Self::new(BufWriter::new(file), TraceFormat::for_path(path)))
+    }
+
+    /// Labels and source lines to annotate the trace with.
+    pub fn set_debug_info(&mut self, info: DebugInfo) {
+        self.info = info;
+    }
+
+    pub fn record(&mut self, record: &TraceRecord) {
+        if self.error.is_some() {
+            return;
+        }
+        let line = match self.format {
+            TraceFormat::Text => record.to_text(&self.info),
+            TraceFormat::Json => record.to_json(&self.info),
+        };
+        if let Err(e) = writeln!(self.out, "{}", line) {
+            self.error = Some(e);
+        }
+    }
+
+    /// Flushes the output, returning the first error hit while tracing.
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> TraceRecord {
+        TraceRecord {
+            pc: 0x3001,
+            instr: 0x3002,
+            registers: vec![],
+            cond: 1,
+            writes: vec![MemoryWrite { address: 0x3004, old: 0, new: 5 }],
+        }
+    }
+
+    fn debug_info() -> DebugInfo {
+        let mut info = DebugInfo::new();
+        info.symbols.insert("MAIN", 0x3000);
+        info.symbols.insert("COUNT", 0x3004);
+        info.lines.insert(0x3001, "main.asm", 4);
+        info
+    }
+
+    #[test]
+    fn test_text() {
+        let none = DebugInfo::new();
+        assert_eq!(record().to_text(&none), "x3001  3002  ST R0, x3004             CC=P  [x3004] x0000 -> x0005");
+
+        let add = TraceRecord { pc: 0x3000, instr: 0x1025, registers: vec![(0, 5)], cond: 1, writes: vec![] };
+        assert_eq!(add.to_text(&none), "x3000  1025  ADD R0, R0, #5           R0=x0005 CC=P");
+
+        assert_eq!(
+            record().to_text(&debug_info()),
+            "x3001  3002  ST R0, COUNT             CC=P  [x3004] x0000 -> x0005  ; MAIN+1 (main.asm:4)"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(
+            record().to_json(&DebugInfo::new()),
+            r#"{"pc":12289,"instr":12290,"asm":"ST R0, x3004","regs":{},"cc":"P","writes":[{"addr":12292,"old":0,"new":5}]}"#
+        );
+
+        let add = TraceRecord { pc: 0x3000, instr: 0x1025, registers: vec![(0, 5), (1, 2)], cond: 1, writes: vec![] };
+        assert!(add.to_json(&DebugInfo::new()).contains(r#""regs":{"R0":5,"R1":2}"#));
+
+        let mut info = debug_info();
+        info.lines.insert(0x3001, "dir\\\"q\".asm", 4);
+        assert!(record().to_json(&info).contains(r#""asm":"ST R0, COUNT","location":"MAIN+1 (dir\\\"q\".asm:4)","regs""#));
+    }
+
+    #[test]
+    fn test_format_for_path() {
+        assert_eq!(TraceFormat::for_path("run.jsonl"), TraceFormat::Json);
+        assert_eq!(TraceFormat::for_path("run.trace"), TraceFormat::Text);
+    }
+}
@@ -1,23 +1,31 @@
 use crate::{memory::Memory, register::Registers};
-use std::io::{stdin, Read, Stdout, Write};
-use termion::raw::*;
+
+/// What the VM should do after a trap routine has run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapOutcome {
+    /// Continue with the next instruction
+    Continue,
+    /// The program executed HALT
+    Halt,
+    /// The routine needs input that is not available yet
+    IoWait,
+}
 
 /// Handles the TRAP instruction execution (opcode 1111)
 /// # Arguments
 /// * `instr` - The 16-bit instruction word containing the trap vector
 /// * `memory` - Mutable reference to the VM's memory
 /// * `registers` - Mutable reference to the VM's registers
-/// * `stdout` - Mutable reference to the terminal output in raw mode
 ///
 /// # Returns
-/// * `true` if execution should continue
-/// * `false` if the program should halt
-pub fn handle_trap(
-    instr: u16,
-    memory: &mut Memory,
-    registers: &mut Registers,
-    stdout: &mut RawTerminal<Stdout>,
-) -> bool {
+/// * `TrapOutcome::Continue` if execution should continue
+/// * `TrapOutcome::Halt` if the program should halt
+/// * `TrapOutcome::IoWait` if input ran out before the routine could finish
+///
+/// All I/O goes through the console attached to `memory`. Write errors are
+/// ignored, as for the display data register: a closed output, such as a pipe
+/// whose reader has gone, discards what the program prints.
+pub fn handle_trap(instr: u16, memory: &mut Memory, registers: &mut Registers) -> TrapOutcome {
     let trap_vector = instr & 0xFF;
     
     // saving the current address to R7 for return
@@ -26,16 +34,19 @@ pub fn handle_trap(
     match trap_vector {
         0x20 => {
             // GETC: Read a single character without echo
-            let stdin = stdin();
-            stdout.flush().unwrap();
-            let c = stdin.lock().bytes().next().unwrap().unwrap();
+            let _ = memory.console().flush();
+            let c = match memory.read_key() {
+                Some(c) => c,
+                None => return TrapOutcome::IoWait,
+            };
             registers.set(0, c as u16);
         }
         0x21 => {
             // OUT: Output a single character
             let char_code = registers.get(0) as u8;
-            write!(stdout, "{}", char_code as char).unwrap();
-            stdout.flush().unwrap();
+            let console = memory.console();
+            let _ = console.write_bytes(&[char_code]);
+            let _ = console.flush();
         }
         0x22 => {
             // PUTS: Output a null-terminated string
@@ -46,19 +57,23 @@ pub fn handle_trap(
                 if ch == 0 {
                     break;
                 }
-                write!(stdout, "{}", (ch & 0xFF) as u8 as char).unwrap();
+                let _ = memory.console().write_bytes(&[(ch & 0xFF) as u8]);
                 addr = addr.wrapping_add(1);
             }
-            stdout.flush().unwrap();
+            let _ = memory.console().flush();
         }
         0x23 => {
             // IN: Input a character with prompt and echo
-            write!(stdout, "Enter a character: ").unwrap();
-            stdout.flush().unwrap();
-            let c = stdin().lock().bytes().next().unwrap().unwrap();
+            let _ = memory.console().write_bytes(b"Enter a character: ");
+            let _ = memory.console().flush();
+            let c = match memory.read_key() {
+                Some(c) => c,
+                None => return TrapOutcome::IoWait,
+            };
             registers.set(0, c as u16);
-            write!(stdout, "{}", c as char).unwrap();
-            stdout.flush().unwrap();
+            let console = memory.console();
+            let _ = console.write_bytes(&[c]);
+            let _ = console.flush();
         }
         0x24 => {
             // PUTSP: Output a null-terminated string packed in 16-bit words
@@ -71,28 +86,35 @@ pub fn handle_trap(
                 if ch1 == 0 {
                     break;
                 }
-                write!(stdout, "{}", ch1 as char).unwrap();
+                let _ = memory.console().write_bytes(&[ch1]);
                 let ch2 = (val >> 8) as u8;
                 if ch2 == 0 {
                     break;
                 }
-                write!(stdout, "{}", ch2 as char).unwrap();
+                let _ = memory.console().write_bytes(&[ch2]);
                 addr = addr.wrapping_add(1);
             }
-            stdout.flush().unwrap();
+            let _ = memory.console().flush();
         }
         0x25 => {
             // HALT: Stops program execution
-            write!(stdout, "\r\nHALT\r\n").unwrap();
-            stdout.flush().unwrap();
-            return false;
+            let _ = memory.console().write_notice("\nHALT\n");
+            return TrapOutcome::Halt;
         }
         _ => {
-            write!(stdout, "TRAP 0x{:02X} not implemented\r\n", trap_vector).unwrap();
-            stdout.flush().unwrap();
+            let _ = memory.console().write_notice(&format!("TRAP 0x{:02X} not implemented\n", trap_vector));
         }
     }
 
     // Continue execution
-    true
-}
\ No newline at end of file
+    TrapOutcome::Continue
+}
+
+/// Executes TRAP the way the LC-3 hardware does, for use with an operating
+/// system image: saves the return address in R7 and jumps to the routine whose
+/// address is stored in the trap vector table at `mem[trapvect8]`.
+pub fn handle_os_trap(instr: u16, memory: &mut Memory, registers: &mut Registers) {
+    registers.set(7, registers.get_pc());
+    let routine = memory.read(instr & 0xFF);
+    registers.set_pc(routine);
+}
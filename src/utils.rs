@@ -12,6 +12,30 @@ pub fn get_bits(value: u16, start: usize, count: usize) -> u16 {
     (value >> start) & ((1 << count) - 1)
 }
 
+// parses a decimal (#10, 10), hex (x3000, 0x3000) or binary (b1010) literal
+pub fn parse_number(text: &str) -> Option<i32> {
+    let (digits, radix) = if let Some(d) = text.strip_prefix('#') {
+        (d, 10)
+    } else if let Some(d) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (d, 16)
+    } else if let Some(d) = text.strip_prefix('x').or_else(|| text.strip_prefix('X')) {
+        (d, 16)
+    } else if let Some(d) = text.strip_prefix('b').or_else(|| text.strip_prefix('B')) {
+        (d, 2)
+    } else {
+        (text, 10)
+    };
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(d) => (true, d),
+        None => (false, digits),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    let value = i32::from_str_radix(digits, radix).ok()?;
+    Some(if negative { -value } else { value })
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -21,4 +45,12 @@ mod tests {
     fn test_sign_extend() {
         assert_eq!(sign_extend(0b1_1010, 5), 0xFFFA);
     }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("#-12"), Some(-12));
+        assert_eq!(parse_number("x3000"), Some(0x3000));
+        assert_eq!(parse_number("b101"), Some(5));
+        assert_eq!(parse_number("xray"), None);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,601 @@
+//! The LC-3 execution core.
+//!
+//! [`Vm`] owns the machine state and runs the fetch/decode/dispatch cycle. Both the
+//! `lc3_vm` binary and library embedders drive programs through it, so they share
+//! the same stop semantics.
+
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+
+use crate::console::Console;
+use crate::coverage::Coverage;
+use crate::history::{History, Undo};
+use crate::loader::{Format, Image, LoadError};
+use crate::memory::{Memory, MemoryWrite, WatchHit, Watchpoint};
+use crate::opcode::{self, OpCode};
+use crate::profile::Profiler;
+use crate::register::{Privilege, Registers};
+use crate::snapshot::Snapshot;
+use crate::trace::{TraceRecord, Tracer};
+use crate::trapcode::{self, TrapOutcome};
+
+/// Address user programs are loaded at and started from by default
+pub const PC_START: u16 = 0x3000;
+
+/// Where an operating system image starts running, in supervisor mode
+pub const OS_ENTRY: u16 = 0x0200;
+
+/// End of system space; an operating system image must fit below it
+pub const OS_END: u16 = 0x2FFF;
+
+/// Start of the interrupt vector table, x0100–x01FF. Exception and interrupt
+/// vectors index into it.
+pub const INTERRUPT_VECTOR_TABLE: u16 = 0x0100;
+
+/// Exception vector raised by RTI in user mode
+pub const PRIVILEGE_MODE_EXCEPTION: u8 = 0x00;
+/// Exception vector raised by the reserved opcode
+pub const ILLEGAL_OPCODE_EXCEPTION: u8 = 0x01;
+
+/// How many instructions [`Vm::run_for`] executes between looks at the clock
+const TIMEOUT_CHECK_INTERVAL: u64 = 4096;
+
+/// Why the VM stopped executing instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The program executed the HALT trap
+    Halted,
+    /// The clock-enable bit of the machine control register (xFFFE) was cleared,
+    /// as an operating system's HALT routine does
+    MachineHalted,
+    /// Execution reached a breakpoint; the instruction at this address has not run yet
+    Breakpoint(u16),
+    /// The instruction at `pc` has the reserved opcode. Only reported when
+    /// [`Vm::set_stop_on_exception`] is on or the vector table has no handler;
+    /// otherwise the exception is raised.
+    IllegalOpcode { pc: u16, instr: u16 },
+    /// The instruction at `pc` is an RTI executed in user mode. Only reported when
+    /// [`Vm::set_stop_on_exception`] is on or the vector table has no handler;
+    /// otherwise the exception is raised.
+    PrivilegeViolation { pc: u16, instr: u16 },
+    /// A trap routine needs input that is not available; stepping again retries it
+    IoWait,
+    /// The instruction at `pc` touched a watched address. It has completed; `hit` is
+    /// the first watched access it made (see [`Memory::watch_hits`] for all of them).
+    Watchpoint { pc: u16, hit: WatchHit },
+    /// The budget set with [`Vm::set_instruction_limit`] is used up
+    InstructionLimit,
+    /// The deadline set with [`Vm::set_timeout`] has passed
+    Timeout,
+}
+
+pub struct Vm {
+    memory: Memory,
+    registers: Registers,
+    breakpoints: BTreeSet<u16>,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    history: History,
+    stop_on_exception: bool,
+    os_traps: bool,
+    instructions: u64,
+    instruction_limit: Option<u64>,
+    deadline: Option<Instant>,
+}
+
+impl Vm {
+    /// Creates a VM with zeroed memory and PC at [`PC_START`].
+    /// All program I/O goes through `console`.
+    pub fn new<C: Console + 'static>(console: C) -> Self {
+        let mut memory = Memory::new();
+        memory.set_console(Box::new(console));
+        let mut registers = Registers::new();
+        registers.set_pc(PC_START);
+        Self {
+            memory,
+            registers,
+            breakpoints: BTreeSet::new(),
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            history: History::new(0),
+            stop_on_exception: false,
+            os_traps: false,
+            instructions: 0,
+            instruction_limit: None,
+            deadline: None,
+        }
+    }
+
+    /// Replaces the console used by the traps and device registers.
+    pub fn set_console<C: Console + 'static>(&mut self, console: C) {
+        self.memory.set_console(Box::new(console));
+    }
+
+    pub fn console(&mut self) -> &mut dyn Console {
+        self.memory.console()
+    }
+
+    /// Loads a program file in any format the [loader](crate::loader) detects and
+    /// points the PC at its first origin, returning that entry point.
+    pub fn load_program(&mut self, path: &str) -> Result<u16, LoadError> {
+        let mut image = Image::new();
+        image.add_file(path, Format::Auto)?;
+        self.load_image(&image);
+        let entry = image.entry().unwrap_or(PC_START);
+        self.registers.set_pc(entry);
+        Ok(entry)
+    }
+
+    /// Copies every segment of `image` into memory, leaving the PC alone.
+    pub fn load_image(&mut self, image: &Image) {
+        image.write_to(&mut self.memory);
+    }
+
+    /// Loads an operating system image into system space (x0000–x2FFF) and boots
+    /// it: the VM switches to supervisor mode with the PC at [`OS_ENTRY`], and TRAP
+    /// jumps through the trap vector table instead of running the built-in routines.
+    ///
+    /// The image may be in any format the [loader](crate::loader) detects.
+    pub fn load_os(&mut self, path: &str) -> Result<(), LoadError> {
+        let mut image = Image::new();
+        image.add_file(path, Format::Auto)?;
+        image.check_within(0x0000, OS_END)?;
+        self.load_image(&image);
+        self.registers.set_privilege(Privilege::Supervisor);
+        self.registers.set_pc(OS_ENTRY);
+        self.set_os_traps(true);
+        Ok(())
+    }
+
+    /// Captures the complete machine state, to be saved or [restored](Vm::restore) later.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut registers = [0; 8];
+        for (r, value) in registers.iter_mut().enumerate() {
+            *value = self.registers.get(r);
+        }
+        Snapshot {
+            registers,
+            pc: self.registers.get_pc(),
+            psr: self.registers.get_psr(),
+            saved_ssp: self.registers.get_saved_ssp(),
+            saved_usp: self.registers.get_saved_usp(),
+            os_traps: self.os_traps,
+            memory: (0..=0xFFFF).map(|addr| self.memory.peek(addr)).collect(),
+        }
+    }
+
+    /// Puts the machine back in the state `snapshot` was taken in. Breakpoints,
+    /// watchpoints, the tracer and the console are left as they are; the
+    /// execution history is cleared.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.history.clear();
+        self.memory.load_words(0, &snapshot.memory);
+        // a PSR store made before the restore must not leak into the next step
+        self.memory.take_psr_write();
+        for (r, &value) in snapshot.registers.iter().enumerate() {
+            self.registers.set(r, value);
+        }
+        self.registers.set_pc(snapshot.pc);
+        self.registers.set_psr(snapshot.psr);
+        self.registers.set_saved_ssp(snapshot.saved_ssp);
+        self.registers.set_saved_usp(snapshot.saved_usp);
+        self.memory.mirror_psr(self.registers.get_psr());
+        self.os_traps = snapshot.os_traps;
+    }
+
+    /// With `enabled` set, TRAP saves the return address in R7 and jumps to
+    /// `mem[trapvect8]` as the hardware does. Otherwise the routines in
+    /// [`trapcode`] run in place of an operating system.
+    pub fn set_os_traps(&mut self, enabled: bool) {
+        self.os_traps = enabled;
+    }
+
+    /// Whether TRAP goes through an operating system, as after [`Vm::load_os`].
+    pub fn os_traps(&self) -> bool {
+        self.os_traps
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    /// Adds a PC breakpoint. Returns `false` if one was already set at `addr`.
+    pub fn add_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    /// Removes a PC breakpoint. Returns `false` if none was set at `addr`.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Adds a memory watchpoint. Returns `false` if an identical one is already set.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        self.memory.add_watchpoint(watchpoint)
+    }
+
+    /// Removes every watchpoint covering `addr`, returning how many were removed.
+    pub fn remove_watchpoints_at(&mut self, addr: u16) -> usize {
+        self.memory.remove_watchpoints_at(addr)
+    }
+
+    /// With `stop` set, exceptions stop the VM with a [`StopReason`] naming the
+    /// faulting instruction instead of jumping to a handler, which suits programs
+    /// run without an operating system.
+    pub fn set_stop_on_exception(&mut self, stop: bool) {
+        self.stop_on_exception = stop;
+    }
+
+    /// Starts recording every executed instruction to `tracer`.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+        self.update_write_logging();
+    }
+
+    /// Stops tracing and hands the tracer back so it can be finished.
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        let tracer = self.tracer.take();
+        self.update_write_logging();
+        tracer
+    }
+
+    /// How many instructions have executed since the VM was created.
+    pub fn instructions_executed(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Makes [`Vm::run`] and [`Vm::run_for`] stop with
+    /// [`StopReason::InstructionLimit`] once `limit` instructions have executed in
+    /// total, counting from the VM's creation. `None` removes the limit.
+    pub fn set_instruction_limit(&mut self, limit: Option<u64>) {
+        self.instruction_limit = limit;
+    }
+
+    /// Makes [`Vm::run`] and [`Vm::run_for`] stop with [`StopReason::Timeout`]
+    /// once `timeout` has passed from now. The clock is only checked every few
+    /// thousand instructions, and not while a trap blocks waiting for a key.
+    /// `None` removes the deadline.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.deadline = timeout.map(|timeout| Instant::now() + timeout);
+    }
+
+    /// Starts counting executed instructions in `profiler`.
+    pub fn set_profiler(&mut self, profiler: Profiler) {
+        self.profiler = Some(profiler);
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Stops profiling and hands the profiler back for its report.
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    /// Starts recording which instructions and branch outcomes execute.
+    pub fn set_coverage(&mut self, coverage: Coverage) {
+        self.coverage = Some(coverage);
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Stops recording coverage and hands it back for reporting.
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    /// Keeps an [`Undo`] record for each of the last `limit` instructions so they
+    /// can be stepped back over; 0 turns the history off.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history.set_limit(limit);
+        self.update_write_logging();
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Reverts the most recently executed instruction, leaving the PC at it.
+    /// Returns its address, or `None` if the history is empty.
+    pub fn step_back(&mut self) -> Option<u16> {
+        let undo = self.history.pop()?;
+        for write in undo.writes.iter().rev() {
+            // put the word back without device side effects or watchpoint hits
+            self.memory.load_words(write.address, &[write.old]);
+        }
+        for &(r, value) in &undo.registers {
+            self.registers.set(r, value);
+        }
+        self.registers.set_pc(undo.pc);
+        self.registers.set_psr(undo.psr);
+        self.registers.set_saved_ssp(undo.saved_ssp);
+        self.registers.set_saved_usp(undo.saved_usp);
+        self.memory.take_psr_write();
+        self.memory.mirror_psr(undo.psr);
+        Some(undo.pc)
+    }
+
+    fn update_write_logging(&mut self) {
+        let enabled = self.tracer.is_some() || self.history.limit() > 0;
+        self.memory.set_write_logging(enabled);
+    }
+
+    /// Executes a single instruction, ignoring breakpoints. A device interrupt
+    /// pending once it completes is taken straight away, leaving the PC at the
+    /// handler.
+    ///
+    /// Returns `None` if execution can carry on, or the reason the machine stopped.
+    pub fn step(&mut self) -> Option<StopReason> {
+        if !self.memory.clock_enabled() {
+            return Some(StopReason::MachineHalted);
+        }
+        let pc = self.registers.get_pc();
+        self.memory.take_watch_hits();
+        // drop stores made outside an instruction, such as interrupt entry
+        self.memory.take_writes();
+        self.sync_psr();
+        let instr = self.memory.peek(pc);
+        let before: [u16; 8] = std::array::from_fn(|r| self.registers.get(r));
+        let undo = Undo {
+            pc,
+            instr,
+            registers: Vec::new(),
+            psr: self.registers.get_psr(),
+            saved_ssp: self.registers.get_saved_ssp(),
+            saved_usp: self.registers.get_saved_usp(),
+            writes: Vec::new(),
+        };
+        let mut reason = self.execute(pc);
+        self.sync_psr();
+        let mut writes = self.memory.take_writes();
+        // these leave the instruction unexecuted, to be retried or reported
+        let executed = !matches!(
+            reason,
+            Some(StopReason::IllegalOpcode { .. } | StopReason::PrivilegeViolation { .. } | StopReason::IoWait)
+        );
+        if executed && self.tracer.is_some() {
+            self.trace(pc, instr, before, writes.clone());
+        }
+        if let Some(profiler) = self.profiler.as_mut().filter(|_| executed) {
+            profiler.record(pc, instr, self.registers.get_pc());
+        }
+        if executed {
+            self.instructions += 1;
+        }
+        if let Some(coverage) = self.coverage.as_mut().filter(|_| executed) {
+            coverage.record(pc, instr, undo.psr & 0x7);
+        }
+        if reason.is_none() {
+            if !self.memory.clock_enabled() {
+                reason = Some(StopReason::MachineHalted);
+            } else {
+                self.service_interrupts();
+                writes.extend(self.memory.take_writes());
+                if let Some(&hit) = self.memory.watch_hits().first() {
+                    reason = Some(StopReason::Watchpoint { pc, hit });
+                }
+            }
+        }
+        if self.history.limit() > 0 {
+            self.remember(undo, before, writes);
+        }
+        reason
+    }
+
+    /// Completes `undo` with what the instruction changed and adds it to the
+    /// history, unless it changed nothing (an exception stop or an input wait).
+    fn remember(&mut self, mut undo: Undo, before: [u16; 8], writes: Vec<MemoryWrite>) {
+        undo.registers = (0..8).filter(|&r| self.registers.get(r) != before[r]).map(|r| (r, before[r])).collect();
+        undo.writes = writes;
+        let unchanged = undo.registers.is_empty()
+            && undo.writes.is_empty()
+            && self.registers.get_pc() == undo.pc
+            && self.registers.get_psr() == undo.psr
+            && self.registers.get_saved_ssp() == undo.saved_ssp
+            && self.registers.get_saved_usp() == undo.saved_usp;
+        if !unchanged {
+            self.history.push(undo);
+        }
+    }
+
+    fn trace(&mut self, pc: u16, instr: u16, before: [u16; 8], writes: Vec<MemoryWrite>) {
+        let record = TraceRecord {
+            pc,
+            instr,
+            registers: (0..8)
+                .filter(|&r| self.registers.get(r) != before[r])
+                .map(|r| (r, self.registers.get(r)))
+                .collect(),
+            cond: self.registers.get_cond_flag(),
+            writes,
+        };
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(&record);
+        }
+    }
+
+    fn execute(&mut self, pc: u16) -> Option<StopReason> {
+        // instruction fetch is not a data access, so it bypasses devices and watchpoints
+        let instr = self.memory.peek(pc);
+        self.registers.increment_pc();
+
+        let (memory, registers) = (&mut self.memory, &mut self.registers);
+        match OpCode::from_instr(instr) {
+            OpCode::ADD => opcode::handle_add(instr, registers),
+            OpCode::AND => opcode::handle_and(instr, registers),
+            OpCode::NOT => opcode::handle_not(instr, registers),
+            OpCode::BR => opcode::handle_br(instr, registers),
+            OpCode::JSR => opcode::handle_jsr(instr, registers),
+            OpCode::LD => opcode::handle_ld(instr, memory, registers),
+            OpCode::LDR => opcode::handle_ldr(instr, memory, registers),
+            OpCode::ST => opcode::handle_st(instr, memory, registers),
+            OpCode::JMP => opcode::handle_jmp(instr, registers),
+            OpCode::LEA => opcode::handle_lea(instr, registers),
+            OpCode::STI => opcode::handle_sti(instr, memory, registers),
+            OpCode::LDI => opcode::handle_ldi(instr, memory, registers),
+            OpCode::STR => opcode::handle_str(instr, memory, registers),
+            OpCode::RTI if registers.get_privilege() == Privilege::User => {
+                let reason = StopReason::PrivilegeViolation { pc, instr };
+                return self.raise_exception(pc, PRIVILEGE_MODE_EXCEPTION, reason);
+            }
+            OpCode::RTI => self.return_from_interrupt(),
+            OpCode::RES => {
+                return self.raise_exception(pc, ILLEGAL_OPCODE_EXCEPTION, StopReason::IllegalOpcode { pc, instr });
+            }
+            OpCode::TRAP if self.os_traps => trapcode::handle_os_trap(instr, memory, registers),
+            OpCode::TRAP => match trapcode::handle_trap(instr, memory, registers) {
+                TrapOutcome::Continue => {}
+                TrapOutcome::Halt => return Some(StopReason::Halted),
+                TrapOutcome::IoWait => {
+                    registers.set_pc(pc);
+                    return Some(StopReason::IoWait);
+                }
+            },
+        }
+        None
+    }
+
+    /// Applies a program store to the memory-mapped PSR, then mirrors the current
+    /// PSR back into memory.
+    fn sync_psr(&mut self) {
+        if let Some(psr) = self.memory.take_psr_write() {
+            self.registers.set_psr(psr);
+        }
+        self.memory.mirror_psr(self.registers.get_psr());
+    }
+
+    fn push(&mut self, value: u16) {
+        let sp = self.registers.get(6).wrapping_sub(1);
+        self.set_stack_pointer(sp);
+        self.memory.write(sp, value);
+    }
+
+    fn pop(&mut self) -> u16 {
+        let sp = self.registers.get(6);
+        self.set_stack_pointer(sp.wrapping_add(1));
+        self.memory.read(sp)
+    }
+
+    /// Moving R6 is not an instruction result, so the condition codes stay.
+    fn set_stack_pointer(&mut self, sp: u16) {
+        let cond = self.registers.get_cond_flag();
+        self.registers.set(6, sp);
+        self.registers.set_cond_flag(cond);
+    }
+
+    /// Takes the pending device interrupt, if its priority beats the PSR's.
+    fn service_interrupts(&mut self) {
+        let Some(interrupt) = self.memory.pending_interrupt() else {
+            return;
+        };
+        if interrupt.priority > self.registers.get_priority() {
+            self.enter_handler(interrupt.vector);
+            self.registers.set_priority(interrupt.priority);
+            self.memory.mirror_psr(self.registers.get_psr());
+        }
+    }
+
+    /// Starts an exception handler for the instruction at `pc`; the priority level
+    /// is unchanged. When stopping on exceptions, or when the vector table entry is
+    /// zero because no operating system installed a handler, rewinds to `pc` and
+    /// returns `reason` instead.
+    fn raise_exception(&mut self, pc: u16, vector: u8, reason: StopReason) -> Option<StopReason> {
+        let handler = self.memory.peek(INTERRUPT_VECTOR_TABLE + vector as u16);
+        if self.stop_on_exception || handler == 0 {
+            self.registers.set_pc(pc);
+            return Some(reason);
+        }
+        self.enter_handler(vector);
+        None
+    }
+
+    /// Enters supervisor mode and jumps through the interrupt vector table. The
+    /// interrupted PSR and PC are pushed on the supervisor stack for RTI.
+    fn enter_handler(&mut self, vector: u8) {
+        let psr = self.registers.get_psr();
+        if self.registers.get_privilege() == Privilege::User {
+            self.registers.switch_to_supervisor_stack();
+        }
+        self.push(psr);
+        self.push(self.registers.get_pc());
+        self.registers.set_privilege(Privilege::Supervisor);
+        let handler = self.memory.read(INTERRUPT_VECTOR_TABLE + vector as u16);
+        self.registers.set_pc(handler);
+    }
+
+    /// RTI in supervisor mode: pops PC and PSR, returning to the user stack if the
+    /// restored PSR is in user mode.
+    fn return_from_interrupt(&mut self) {
+        let pc = self.pop();
+        let psr = self.pop();
+        self.registers.set_pc(pc);
+        self.registers.set_psr(psr);
+        if self.registers.get_privilege() == Privilege::User {
+            self.registers.switch_to_user_stack();
+        }
+    }
+
+    /// Whether the instruction budget or the deadline has run out. `check_clock`
+    /// forces a look at the clock between the usual intervals.
+    fn limit_reached(&self, check_clock: bool) -> Option<StopReason> {
+        if self.instruction_limit.is_some_and(|limit| self.instructions >= limit) {
+            return Some(StopReason::InstructionLimit);
+        }
+        let check_clock = check_clock || self.instructions.is_multiple_of(TIMEOUT_CHECK_INTERVAL);
+        if check_clock && self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Some(StopReason::Timeout);
+        }
+        None
+    }
+
+    /// Runs until the program stops.
+    pub fn run(&mut self) -> StopReason {
+        loop {
+            if let Some(reason) = self.run_for(usize::MAX) {
+                return reason;
+            }
+        }
+    }
+
+    /// Executes at most `n_instructions` instructions.
+    ///
+    /// Breakpoints are checked before every instruction except the first, so calling
+    /// this again after a [`StopReason::Breakpoint`] resumes past it. Returns `None`
+    /// if the budget ran out before the program stopped.
+    pub fn run_for(&mut self, n_instructions: usize) -> Option<StopReason> {
+        for i in 0..n_instructions {
+            let pc = self.registers.get_pc();
+            if i > 0 && self.breakpoints.contains(&pc) {
+                return Some(StopReason::Breakpoint(pc));
+            }
+            if let Some(reason) = self.limit_reached(i == 0) {
+                return Some(reason);
+            }
+            if let Some(reason) = self.step() {
+                return Some(reason);
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,114 @@
+use lc3_vm::assembler::{assemble, AsmError};
+use lc3_vm::console::BufferConsole;
+use lc3_vm::symbols::{DebugInfo, LineMap, SymbolTable};
+use lc3_vm::{StopReason, Vm};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encodes_every_instruction() {
+        let source = "
+            .ORIG x3000
+    START   ADD R1, R2, R3
+            ADD R1, R2, #-1
+            AND R0, R0, #0
+            NOT R4, R5
+            BRnp START
+            BR START
+            JMP R2
+            RET
+            JSR START
+            JSRR R3
+            LD R1, START
+            LDI R1, START
+            LDR R1, R2, #-32
+            LEA R1, START
+            ST R1, START
+            STI R1, START
+            STR R1, R2, #31
+            TRAP x25
+            RTI
+            GETC
+            .END
+        ";
+        let program = assemble(source).unwrap();
+
+        assert_eq!(program.origin, 0x3000);
+        assert_eq!(program.words, vec![
+            0x1283, 0x12BF, 0x5020, 0x997F, 0x0BFB, 0x0FFA, 0xC080, 0xC1C0,
+            0x4FF7, 0x40C0, 0x23F5, 0xA3F4, 0x62A0, 0xE3F2, 0x33F1, 0xB3F0,
+            0x729F, 0xF025, 0x8000, 0xF020,
+        ]);
+    }
+
+    #[test]
+    fn test_directives() {
+        let source = "
+            .orig x4000
+            .FILL xBEEF
+            .FILL #-1
+            .FILL DATA
+            .BLKW 2
+    DATA:   .STRINGZ \"a\\n\"
+            .END
+            ADD R0, R0, R0 ; ignored after .END
+        ";
+        let program = assemble(source).unwrap();
+
+        assert_eq!(program.origin, 0x4000);
+        assert_eq!(program.words, vec![0xBEEF, 0xFFFF, 0x4005, 0, 0, 0x61, 0x0A, 0]);
+        assert_eq!(program.to_obj_bytes()[..4], [0x40, 0x00, 0xBE, 0xEF]);
+    }
+
+    #[test]
+    fn test_assembled_program_runs() {
+        let source = "
+            .ORIG x3000
+            LEA R0, MSG
+            PUTS
+            HALT
+    MSG     .STRINGZ \"Hi\"
+            .END
+        ";
+        let program = assemble(source).unwrap();
+
+        let console = BufferConsole::new();
+        let mut vm = Vm::new(console.clone());
+        for (i, word) in program.words.iter().enumerate() {
+            vm.memory_mut().write(program.origin + i as u16, *word);
+        }
+
+        assert_eq!(vm.run(), StopReason::Halted);
+        assert!(console.output().starts_with(b"Hi"));
+    }
+
+    #[test]
+    fn test_symbols_and_line_map() {
+        let source = ".ORIG x3000\n\nSTART ADD R0, R0, #1\nLOOP  BRnzp LOOP\n.END\n";
+        let program = assemble(source).unwrap();
+
+        assert_eq!(program.symbols.address_of("START"), Some(0x3000));
+        assert_eq!(program.symbols.address_of("LOOP"), Some(0x3001));
+        assert_eq!(program.lines, vec![(0x3000, 3), (0x3001, 4)]);
+
+        let info = DebugInfo {
+            symbols: SymbolTable::parse_sym(&program.symbols.to_sym()).unwrap(),
+            lines: LineMap::parse_dbg(&program.line_map("main.asm").to_dbg()).unwrap(),
+        };
+        assert_eq!(info.describe(0x3001), "LOOP (main.asm:4)");
+    }
+
+    #[test]
+    fn test_errors_report_line() {
+        let err = |source: &str| assemble(source).unwrap_err();
+
+        assert_eq!(err("ADD R0, R0, R0"), AsmError { line: 1, message: "expected .ORIG before any other statement".into() });
+        assert_eq!(err(".ORIG x3000\nBRz NOWHERE\n.END").line, 2);
+        assert_eq!(err(".ORIG x3000\nADD R0, R0, #16\n.END").message, "value 16 does not fit in 5 bits");
+        assert_eq!(err(".ORIG x3000\nADD R8, R0, R0\n.END").message, "expected a register, found 'R8'");
+        assert_eq!(err(".ORIG x3000\nX HALT\nX HALT\n.END").message, "duplicate label 'X'");
+        assert_eq!(err(".ORIG x3000\nFOO R1\n.END").message, "unknown instruction 'R1'");
+    }
+}
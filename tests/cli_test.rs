@@ -0,0 +1,167 @@
+mod common;
+
+use common::TempFile;
+use lc3_vm::assembler::assemble;
+use lc3_vm::cli::{self, Command, Mode, RunOptions};
+use lc3_vm::console::NullConsole;
+use lc3_vm::{StopReason, Vm};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_run() {
+        let expected = RunOptions {
+            programs: vec!["a.obj".into(), "lib.obj".into()],
+            entry: Some("MAIN".into()),
+            trace: Some("t.jsonl".into()),
+            profile: Some("p.txt".into()),
+            mode: Mode::Gdb(1234),
+            ..RunOptions::default()
+        };
+        assert_eq!(cli::parse(&args("run a.obj lib.obj --entry MAIN --trace t.jsonl --profile p.txt --gdb 1234")), Ok(Command::Run(Box::new(expected))));
+
+        let Ok(Command::Run(options)) = cli::parse(&args("--debug prog.obj")) else {
+            panic!("expected a run command");
+        };
+        assert_eq!(options.mode, Mode::Debug);
+        assert_eq!(options.programs, ["prog.obj"]);
+
+        assert_eq!(cli::parse(&args("a.obj --help")), Ok(Command::Help));
+        assert_eq!(
+            cli::parse(&args("asm main.asm -o out.obj")),
+            Ok(Command::Assemble { input: "main.asm".into(), output: Some("out.obj".into()) })
+        );
+        assert_eq!(
+            cli::parse(&args("convert prog.hex prog.obj")),
+            Ok(Command::Convert { input: "prog.hex".into(), output: "prog.obj".into() })
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(cli::parse(&args("")), Err("no program to run".to_string()));
+        assert_eq!(cli::parse(&args("a.obj --entry")), Err("--entry needs a value".to_string()));
+        assert_eq!(cli::parse(&args("a.obj --fast")), Err("unknown option '--fast'".to_string()));
+        assert_eq!(cli::parse(&args("a.obj --gdb port")), Err("invalid port 'port'".to_string()));
+        assert!(cli::parse(&args("a.obj --debug --gdb 1")).is_err());
+        assert!(cli::parse(&args("a.obj --os os.obj --entry x3000")).is_err());
+        assert!(cli::parse(&args("asm")).is_err());
+        assert!(cli::parse(&args("--restore game.snap --os os.obj")).is_err());
+        assert!(cli::parse(&args("a.obj --headless --debug")).is_err());
+        assert_eq!(cli::parse(&args("a.obj --input abc")), Err("--input and --output need --headless".to_string()));
+    }
+
+    #[test]
+    fn test_load_programs_with_entry_label() {
+        let dir = TempFile::new("programs");
+        std::fs::create_dir_all(dir.path()).unwrap();
+        let main = "
+            .ORIG x3000
+            LD R1, LIB
+            JSRR R1
+            HALT
+    LIB     .FILL x4000
+            .END
+        ";
+        let lib = "
+            .ORIG x4000
+    SQUARE  ADD R0, R0, #3
+            RET
+    START   LD R0, #-3
+            JSR SQUARE
+            HALT
+            .END
+        ";
+        let mut options = RunOptions::default();
+        for (name, source) in [("main.obj", main), ("lib.obj", lib)] {
+            let program = assemble(source).unwrap();
+            let path = dir.path().join(name);
+            std::fs::write(&path, program.to_obj_bytes()).unwrap();
+            std::fs::write(path.with_extension("sym"), program.symbols.to_sym()).unwrap();
+            options.programs.push(path.to_str().unwrap().to_string());
+        }
+        let mut vm = Vm::new(NullConsole);
+        options.load(&mut vm).unwrap();
+        assert_eq!(vm.registers().get_pc(), 0x3000);
+        assert_eq!(vm.run(), StopReason::Halted);
+        assert_eq!(vm.registers().get(0), 3);
+
+        options.entry = Some("START".into());
+        let mut vm = Vm::new(NullConsole);
+        options.load(&mut vm).unwrap();
+        assert_eq!(vm.registers().get_pc(), 0x4002);
+
+        options.entry = Some("NOWHERE".into());
+        assert_eq!(options.load(&mut Vm::new(NullConsole)), Err("unknown label 'NOWHERE'".to_string()));
+
+        options.entry = None;
+        options.programs.push(options.programs[0].clone());
+        assert!(options.load(&mut Vm::new(NullConsole)).unwrap_err().contains("overlaps"));
+    }
+
+    #[test]
+    fn test_restore_snapshot() {
+        let file = TempFile::new("state.snap");
+        let path = file.path();
+        let mut vm = Vm::new(NullConsole);
+        vm.memory_mut().write(0x3100, 0xF025);
+        vm.registers_mut().set_pc(0x3100);
+        vm.registers_mut().set(3, 42);
+        vm.snapshot().save(path).unwrap();
+
+        let Ok(Command::Run(options)) = cli::parse(&args(&format!("--restore {}", path.display()))) else {
+            panic!("expected a run command");
+        };
+        let mut vm = Vm::new(NullConsole);
+        options.load(&mut vm).unwrap();
+        assert_eq!(vm.registers().get_pc(), 0x3100);
+        assert_eq!(vm.registers().get(3), 42);
+        assert_eq!(vm.run(), StopReason::Halted);
+    }
+
+    #[test]
+    fn test_headless_options() {
+        let Ok(Command::Run(options)) = cli::parse(&args("run --headless --input abc --output out.txt a.obj")) else {
+            panic!("expected a run command");
+        };
+        assert_eq!(options.mode, Mode::Headless);
+        assert_eq!(options.output.as_deref(), Some("out.txt"));
+        assert_eq!(options.input_bytes(), Ok(b"abc".to_vec()));
+
+        let file = TempFile::new("input.txt");
+        let path = file.path();
+        std::fs::write(path, "from a file\n").unwrap();
+        let options = RunOptions { input: Some(path.to_str().unwrap().to_string()), ..*options };
+        assert_eq!(options.input_bytes(), Ok(b"from a file\n".to_vec()));
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(cli::exit_code(StopReason::Halted), cli::EXIT_HALTED);
+        assert_eq!(cli::exit_code(StopReason::IllegalOpcode { pc: 0x3000, instr: 0xD000 }), cli::EXIT_FAULT);
+        assert_eq!(cli::exit_code(StopReason::IoWait), cli::EXIT_INPUT_EXHAUSTED);
+        assert_eq!(cli::exit_code(StopReason::InstructionLimit), cli::EXIT_INSTRUCTION_LIMIT);
+        assert_eq!(cli::exit_code(StopReason::Timeout), cli::EXIT_TIMEOUT);
+    }
+
+    #[test]
+    fn test_limits() {
+        let Ok(Command::Run(options)) = cli::parse(&args("a.obj --max-instructions 1000000 --timeout 2.5")) else {
+            panic!("expected a run command");
+        };
+        assert_eq!(options.max_instructions, Some(1_000_000));
+        assert_eq!(options.timeout, Some(std::time::Duration::from_millis(2500)));
+
+        assert_eq!(cli::parse(&args("a.obj --timeout -1")), Err("invalid timeout '-1'".to_string()));
+        assert_eq!(
+            cli::parse(&args("a.obj --max-instructions lots")),
+            Err("invalid instruction count 'lots'".to_string())
+        );
+    }
+}
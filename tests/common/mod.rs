@@ -0,0 +1,31 @@
+//! Helpers shared by the test crates.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A path in the temp directory that no concurrent test run shares. Whatever
+/// the test creates there, file or directory, is removed when this is dropped,
+/// even if the test fails first.
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    pub fn new(name: &str) -> Self {
+        Self { path: std::env::temp_dir().join(format!("lc3_vm_test_{}_{}", std::process::id(), name)) }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if self.path.is_dir() {
+            let _ = fs::remove_dir_all(&self.path);
+        } else {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
@@ -0,0 +1,198 @@
+mod common;
+
+use common::TempFile;
+use lc3_vm::assembler::assemble;
+use lc3_vm::console::BufferConsole;
+use lc3_vm::debugger::Debugger;
+use lc3_vm::symbols::DebugInfo;
+use lc3_vm::Vm;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "
+        .ORIG x3000
+        AND R0, R0, #0
+LOOP    ADD R0, R0, #1
+        JSR DOUBLE
+        ADD R1, R0, #-8
+        BRn LOOP
+        HALT
+DOUBLE  ADD R0, R0, R0
+        RET
+        .END
+    ";
+
+    fn debugger(console: BufferConsole) -> Debugger {
+        let program = assemble(SOURCE).unwrap();
+        let mut vm = Vm::new(console);
+        for (i, word) in program.words.iter().enumerate() {
+            vm.memory_mut().write(program.origin + i as u16, *word);
+        }
+        let info = DebugInfo { symbols: program.symbols.clone(), lines: program.line_map("loop.asm") };
+        Debugger::new(vm, info)
+    }
+
+    #[test]
+    fn test_step_and_next() {
+        let mut db = debugger(BufferConsole::new());
+
+        assert_eq!(db.execute("step 2"), "LOOP+1 (loop.asm:5): x3002  4803  JSR DOUBLE\n");
+        assert_eq!(db.execute("step"), "DOUBLE (loop.asm:9): x3006  1000  ADD R0, R0, R0\n");
+        db.execute("s 2");
+        assert_eq!(db.vm().registers().get_pc(), 0x3003);
+
+        db.execute("next 3");
+        db.execute("next");
+        assert_eq!(db.vm().registers().get_pc(), 0x3003); // stepped over the JSR
+        assert_eq!(db.vm().registers().get(0), 6);
+    }
+
+    #[test]
+    fn test_breakpoints_and_continue() {
+        let mut db = debugger(BufferConsole::new());
+
+        assert_eq!(db.execute("break DOUBLE"), "Breakpoint set at DOUBLE (loop.asm:9)\n");
+        assert!(db.execute("continue").starts_with("Breakpoint at DOUBLE (loop.asm:9)\n"));
+        assert_eq!(db.vm().registers().get(0), 1);
+
+        assert_eq!(db.execute("delete x3006"), "Deleted breakpoint at DOUBLE (loop.asm:9)\n");
+        assert_eq!(db.execute("c"), "Program stopped: halted\n");
+        assert_eq!(db.execute("c"), "error: the program is no longer running (halted)\n");
+    }
+
+    #[test]
+    fn test_inspect_and_set() {
+        let mut db = debugger(BufferConsole::new());
+
+        db.execute("set reg R3 xBEEF");
+        db.execute("set reg PC DOUBLE");
+        db.execute("set mem x4000 #-1");
+        assert!(db.execute("regs").contains("R3 xBEEF"));
+        assert!(db.execute("regs").contains("PC x3006"));
+        assert_eq!(db.execute("mem x4000 2"), "x4000: FFFF 0000\n");
+        assert_eq!(db.execute("disas DOUBLE 1"), "x3006  1000  DOUBLE       ADD R0, R0, R0\n");
+        assert_eq!(db.execute("break NOWHERE"), "error: unknown label 'NOWHERE'\n");
+        assert_eq!(db.execute("break x10000"), "error: 'x10000' is not an address\n");
+        assert_eq!(db.execute("break #-1"), "error: '#-1' is not an address\n");
+    }
+
+    #[test]
+    fn test_repl_reads_console() {
+        let console = BufferConsole::with_input(b"b x3005\nc\nquit\nstep\n");
+        let mut db = debugger(console.clone());
+        db.repl();
+
+        let output = String::from_utf8(console.output()).unwrap();
+        assert!(output.contains("Breakpoint at LOOP+4 (loop.asm:8)"));
+        assert_eq!(db.vm().registers().get_pc(), 0x3005);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let source = "
+            .ORIG x3000
+            LEA R1, DATA
+            AND R0, R0, #0
+            ADD R0, R0, #5
+            STR R0, R1, #0
+            LDR R2, R1, #0
+            HALT
+    DATA    .FILL #0
+            .END
+        ";
+        let program = assemble(source).unwrap();
+        let mut vm = Vm::new(BufferConsole::new());
+        for (i, word) in program.words.iter().enumerate() {
+            vm.memory_mut().write(program.origin + i as u16, *word);
+        }
+        let info = DebugInfo { symbols: program.symbols.clone(), lines: program.line_map("data.asm") };
+        let mut db = Debugger::new(vm, info);
+
+        assert_eq!(db.execute("watch DATA"), "Watchpoint (write) set on DATA (data.asm:9)\n");
+        assert_eq!(db.execute("awatch x2000 2"), "Watchpoint (access) set on x2000..x2001\n");
+        assert!(db.execute("continue").starts_with(
+            "Watchpoint hit by STR R0, R1, #0 (x3003 (data.asm:6)): wrote DATA (data.asm:9) x0000 -> x0005\n"
+        ));
+
+        assert_eq!(db.execute("unwatch DATA"), "Deleted 1 watchpoint(s)\n");
+        db.execute("rwatch DATA");
+        assert!(db.execute("c").starts_with("Watchpoint hit by LDR R2, R1, #0 (x3004 (data.asm:7)): read DATA (data.asm:9) x0005\n"));
+        assert_eq!(db.execute("c"), "Program stopped: halted\n");
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let file = TempFile::new("debugger.snap");
+        let path = file.path().to_str().unwrap();
+        let mut db = debugger(BufferConsole::new());
+
+        db.execute("step 2");
+        assert_eq!(db.execute(&format!("save {}", path)), format!("Saved machine state to {}\n", path));
+        assert_eq!(db.execute("c"), "Program stopped: halted\n");
+
+        assert_eq!(
+            db.execute(&format!("load {}", path)),
+            format!("Restored machine state from {}\nLOOP+1 (loop.asm:5): x3002  4803  JSR DOUBLE\n", path)
+        );
+        assert_eq!(db.vm().registers().get(0), 1);
+        assert_eq!(db.execute("c"), "Program stopped: halted\n");
+        assert_eq!(db.vm().registers().get(0), 14);
+
+        assert!(db.execute("load /nonexistent/snap").starts_with("error: /nonexistent/snap: "));
+        assert_eq!(db.execute("save"), "error: usage: save <file>\n");
+    }
+
+    #[test]
+    fn test_reverse_execution() {
+        let mut db = debugger(BufferConsole::new());
+        assert_eq!(db.execute("rs"), "error: no recorded history to step back over\n");
+
+        db.execute("break DOUBLE");
+        db.execute("c");
+        db.execute("c");
+        assert_eq!(db.vm().registers().get(0), 3);
+        assert_eq!(db.execute("reverse-step"), "LOOP+1 (loop.asm:5): x3002  4803  JSR DOUBLE\n");
+        assert_eq!(db.vm().registers().get(0), 3);
+        assert_eq!(db.execute("rc"), "Breakpoint at DOUBLE (loop.asm:9)\nDOUBLE (loop.asm:9): x3006  1000  ADD R0, R0, R0\n");
+        assert_eq!(db.vm().registers().get(0), 1);
+
+        db.execute("delete");
+        assert_eq!(db.execute("c"), "Program stopped: halted\n");
+        assert!(db.execute("rs").ends_with("x3005  F025  HALT\n"));
+        assert_eq!(db.execute("c"), "Program stopped: halted\n");
+        assert_eq!(
+            db.execute("reverse-continue"),
+            "Reached the start of the recorded history\nx3000 (loop.asm:3): x3000  5020  AND R0, R0, #0\n"
+        );
+        assert_eq!(db.vm().registers().get(0), 0);
+    }
+
+    #[test]
+    fn test_last_write() {
+        let source = "
+            .ORIG x3000
+            AND R0, R0, #0
+            ADD R0, R0, #7
+            ST R0, DATA
+            HALT
+    DATA    .FILL #0
+            .END
+        ";
+        let program = assemble(source).unwrap();
+        let mut vm = Vm::new(BufferConsole::new());
+        for (i, word) in program.words.iter().enumerate() {
+            vm.memory_mut().write(program.origin + i as u16, *word);
+        }
+        let info = DebugInfo { symbols: program.symbols.clone(), lines: program.line_map("data.asm") };
+        let mut db = Debugger::new(vm, info);
+
+        assert_eq!(db.execute("lastwrite DATA"), "No recorded write to DATA (data.asm:7)\n");
+        db.execute("c");
+        assert_eq!(
+            db.execute("lastwrite DATA"),
+            "DATA (data.asm:7) last written by ST R0, DATA (x3002 (data.asm:5)): x0000 -> x0007\n"
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use lc3_vm::console::{BufferConsole, NullConsole};
+use lc3_vm::gdb::GdbServer;
+use lc3_vm::Vm;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minimal RSP client: sends each packet and collects the replies.
+    fn client(addr: SocketAddr, packets: &'static [&'static str]) -> thread::JoinHandle<Vec<String>> {
+        thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let mut replies = Vec::new();
+            for packet in packets {
+                let checksum = packet.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+                write!(stream, "${}#{:02x}", packet, checksum).unwrap();
+                if *packet == "k" {
+                    break;
+                }
+                let mut reply = Vec::new();
+                let mut byte = [0];
+                // skip the ack, then read up to and including the checksum
+                while reply.last() != Some(&b'#') {
+                    stream.read_exact(&mut byte).unwrap();
+                    if byte[0] != b'+' || !reply.is_empty() {
+                        reply.push(byte[0]);
+                    }
+                }
+                let mut checksum = [0; 2];
+                stream.read_exact(&mut checksum).unwrap();
+                stream.write_all(b"+").unwrap();
+                replies.push(String::from_utf8(reply[1..reply.len() - 1].to_vec()).unwrap());
+            }
+            replies
+        })
+    }
+
+    fn vm_with(words: &[u16]) -> Vm {
+        let mut vm = Vm::new(NullConsole);
+        for (i, word) in words.iter().enumerate() {
+            vm.memory_mut().write(0x3000 + i as u16, *word);
+        }
+        vm
+    }
+
+    #[test]
+    fn test_registers_memory_and_stepping() {
+        let mut vm = vm_with(&[
+            0b0001000000100101, // ADD R0, R0, #5
+            0b0011000000000010, // ST R0, #2
+            0xF025,             // HALT
+        ]);
+        let server = GdbServer::bind(0).unwrap();
+        let session = client(server.local_addr().unwrap(), &[
+            "qSupported:swbreak+",
+            "?",
+            "m3000,2",
+            "s",
+            "p0",
+            "g",
+            "P1=beef",
+            "p1",
+            "Z2,3004,1",
+            "c",
+            "z2,3004,1",
+            "M4000,1:1234",
+            "m4000,1",
+            "m0,1001",
+            "m0,ffffffffffff",
+            "M4000,1001:1234",
+            "c",
+            "k",
+        ]);
+        server.serve(&mut vm).unwrap();
+
+        assert_eq!(session.join().unwrap(), vec![
+            "PacketSize=4000;QStartNoAckMode+;hwbreak+;qXfer:features:read+",
+            "S05",
+            "10253002",
+            "S05",
+            "0005",
+            "0005000000000000000000000000000030018001",
+            "OK",
+            "beef",
+            "OK",
+            "T05watch:3004;",
+            "OK",
+            "OK",
+            "1234",
+            "E01",
+            "E01",
+            "E01",
+            "W00",
+        ]);
+        assert_eq!(vm.registers().get(1), 0xBEEF);
+        assert_eq!(vm.memory().peek(0x3004), 5);
+    }
+
+    #[test]
+    fn test_memory_writes_skip_devices() {
+        let console = BufferConsole::new();
+        let mut vm = Vm::new(console.clone());
+        vm.memory_mut().write(0x3000, 0xF025); // HALT
+        let server = GdbServer::bind(0).unwrap();
+        let session = client(server.local_addr().unwrap(), &[
+            "Mfe06,1:0041", // display data
+            "Mfffe,1:0000", // machine control
+            "Mfffc,1:8001", // PSR: user mode, P
+            "mfe06,1",
+            "p9",
+            "mfffc,1",
+            "c",
+            "k",
+        ]);
+        server.serve(&mut vm).unwrap();
+
+        assert_eq!(session.join().unwrap(), vec!["OK", "OK", "OK", "0041", "8001", "8001", "W00"]);
+        assert!(console.output().is_empty());
+    }
+
+    #[test]
+    fn test_target_description() {
+        let mut vm = vm_with(&[0xF025]);
+        let server = GdbServer::bind(0).unwrap();
+        let session = client(server.local_addr().unwrap(), &[
+            "qXfer:features:read:target.xml:0,15",
+            "qXfer:features:read:target.xml:15,1000",
+            "qXfer:features:read:other.xml:0,15",
+            "k",
+        ]);
+        server.serve(&mut vm).unwrap();
+
+        let replies = session.join().unwrap();
+        assert_eq!(replies[0], "m<?xml version=\"1.0\"?>");
+        assert!(replies[1].starts_with("l\n<!DOCTYPE target"));
+        assert!(replies[1].contains("<reg name=\"r0\" bitsize=\"16\" type=\"int16\" regnum=\"0\"/>"));
+        assert!(replies[1].contains("<reg name=\"psr\" bitsize=\"16\" type=\"uint16\"/>"));
+        assert_eq!(replies[2], "E01");
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut vm = vm_with(&[
+            0b0001000000100001, // ADD R0, R0, #1
+            0b0000111111111110, // BRnzp #-2
+        ]);
+        let server = GdbServer::bind(0).unwrap();
+        let session = client(server.local_addr().unwrap(), &[
+            "Z0,3001,2",
+            "c",
+            "c",
+            "p0",
+            "z0,3001,2",
+            "p8",
+            "D",
+        ]);
+        server.serve(&mut vm).unwrap();
+
+        assert_eq!(session.join().unwrap(), vec!["OK", "S05", "S05", "0002", "OK", "3001", "OK"]);
+    }
+}
@@ -39,7 +39,7 @@ mod tests {
         registers.set_pc(0x3000);
         registers.set(0, 0); // This will set the ZRO flag
         
-        let instructions = vec![
+        let instructions = [
             0b0000010000000101, // BRz #5 ; Branch if zero (PC = 0x3000 + 5 = 0x3005)
         ];
         
@@ -50,7 +50,7 @@ mod tests {
         registers.set_pc(0x3000);
         registers.update_flags(0); // This should set the Z flag since value is zero
         
-        let instructions = vec![
+        let instructions = [
             0b0000010000000101, // BRz #5 ; Branch if zero (PC = 0x3000 + 5 = 0x3005)
         ];
         
@@ -65,7 +65,7 @@ mod tests {
         // Set base register value
         registers.set(3, 0x4000);
         
-        let instructions = vec![
+        let instructions = [
             0b1100000011000000, // JMP R3 ; Jump to address in R3 (PC = 0x4000)
         ];
         
@@ -79,7 +79,7 @@ mod tests {
         
         registers.set_pc(0x3000);
         
-        let instructions = vec![
+        let instructions = [
             0b0100100000010100, // JSR #20 ; Jump to subroutine at offset 20, save PC in R7
         ];
         
@@ -91,7 +91,7 @@ mod tests {
         registers.set_pc(0x3000);
         registers.set(2, 0x4000);
         
-        let instructions = vec![
+        let instructions = [
             0b0100000010000000, // JSRR R2 ; Jump to subroutine at address in R2, save PC in R7
         ];
         
@@ -108,7 +108,7 @@ mod tests {
         registers.set_pc(0x3000);
         memory.write(0x3005, 42); // Write value 42 at address 0x3005
         
-        let instructions = vec![
+        let instructions = [
             0b0010001000000101, // LD R1, #5 ; Load from 0x3000 + 5 into R1
             0b0011010000001010, // ST R2, #10 ; Store R2 to address 0x3000 + 10 = 0x300A
         ];
@@ -129,7 +129,7 @@ mod tests {
         registers.set(3, 0x4000); // Base register
         memory.write(0x4005, 42); // Write value 42 at address 0x4005
         
-        let instructions = vec![
+        let instructions = [
             0b0110001011000101, // LDR R1, R3, #5 ; Load from 0x4000 + 5 into R1
             0b0111010011001010, // STR R2, R3, #10 ; Store R2 to address 0x4000 + 10 = 0x400A
         ];
@@ -151,7 +151,7 @@ mod tests {
         memory.write(0x3005, 0x4000); // Address to indirect memory location
         memory.write(0x4000, 42);     // Value at indirect location
         
-        let instructions = vec![
+        let instructions = [
             0b1010001000000101, // LDI R1, #5 ; Load from memory[memory[0x3000 + 5]] into R1
             0b1011010000001010, // STI R2, #10 ; Store R2 to memory[memory[0x3000 + 10]]
         ];
@@ -171,7 +171,7 @@ mod tests {
         
         registers.set(1, 0b1010);
         
-        let instructions = vec![
+        let instructions = [
             0b1001010001111111, // NOT R2, R1 ; R2 = ~R1 = ~0b1010 = 0b...11110101
         ];
         
@@ -185,7 +185,7 @@ mod tests {
         
         registers.set_pc(0x3000);
         
-        let instructions = vec![
+        let instructions = [
             0b1110001000000101, // LEA R1, #5 ; R1 = PC + 5 = 0x3000 + 5 = 0x3005
         ];
         
@@ -0,0 +1,153 @@
+use lc3_vm::console::{BufferConsole, Console};
+use lc3_vm::memory::Memory;
+use lc3_vm::register::Registers;
+use lc3_vm::trapcode::{self, TrapOutcome};
+
+use std::io;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup(input: &[u8]) -> (Memory, Registers, BufferConsole) {
+        let console = BufferConsole::with_input(input);
+        let mut memory = Memory::new();
+        memory.set_console(Box::new(console.clone()));
+        let mut registers = Registers::new();
+        registers.set_pc(0x3001);
+        (memory, registers, console)
+    }
+
+    #[test]
+    fn test_getc_and_out() {
+        let (mut memory, mut registers, console) = setup(b"x");
+
+        assert_eq!(trapcode::handle_trap(0xF020, &mut memory, &mut registers), TrapOutcome::Continue);
+        assert_eq!(registers.get(0), b'x' as u16);
+        assert_eq!(registers.get(7), 0x3001); // return address
+
+        registers.set(0, b'y' as u16);
+        trapcode::handle_trap(0xF021, &mut memory, &mut registers);
+        assert_eq!(console.output(), b"y");
+    }
+
+    #[test]
+    fn test_getc_without_input() {
+        let (mut memory, mut registers, _console) = setup(b"");
+        assert_eq!(trapcode::handle_trap(0xF020, &mut memory, &mut registers), TrapOutcome::IoWait);
+    }
+
+    #[test]
+    fn test_puts_and_putsp() {
+        let (mut memory, mut registers, console) = setup(b"");
+
+        for (i, ch) in b"Hi\0".iter().enumerate() {
+            memory.write(0x4000 + i as u16, *ch as u16);
+        }
+        registers.set(0, 0x4000);
+        trapcode::handle_trap(0xF022, &mut memory, &mut registers);
+        assert_eq!(console.take_output(), b"Hi");
+
+        memory.write(0x5000, 0x6548); // 'H' 'e'
+        memory.write(0x5001, 0x0079); // 'y'
+        registers.set(0, 0x5000);
+        trapcode::handle_trap(0xF024, &mut memory, &mut registers);
+        assert_eq!(console.take_output(), b"Hey");
+    }
+
+    #[test]
+    fn test_halt() {
+        let (mut memory, mut registers, console) = setup(b"");
+        assert_eq!(trapcode::handle_trap(0xF025, &mut memory, &mut registers), TrapOutcome::Halt);
+        assert_eq!(console.output(), b"\nHALT\n");
+    }
+
+    /// Keeps the VM's notices apart from program output, as a headless run does.
+    struct Notices {
+        output: BufferConsole,
+        notices: BufferConsole,
+    }
+
+    impl Console for Notices {
+        fn read_char(&mut self) -> Option<u8> {
+            None
+        }
+
+        fn poll_char(&mut self) -> Option<u8> {
+            None
+        }
+
+        fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+            self.output.write_bytes(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn write_notice(&mut self, text: &str) -> io::Result<()> {
+            self.notices.write_bytes(text.as_bytes())
+        }
+    }
+
+    /// Output whose reader has gone away.
+    struct ClosedConsole;
+
+    impl Console for ClosedConsole {
+        fn read_char(&mut self) -> Option<u8> {
+            None
+        }
+
+        fn poll_char(&mut self) -> Option<u8> {
+            None
+        }
+
+        fn write_bytes(&mut self, _bytes: &[u8]) -> io::Result<()> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+    }
+
+    #[test]
+    fn test_closed_output() {
+        let mut memory = Memory::new();
+        memory.set_console(Box::new(ClosedConsole));
+        let mut registers = Registers::new();
+
+        memory.write(0x4000, b'H' as u16);
+        registers.set(0, 0x4000);
+        for instr in [0xF021, 0xF022, 0xF024, 0xF0FF] {
+            assert_eq!(trapcode::handle_trap(instr, &mut memory, &mut registers), TrapOutcome::Continue);
+        }
+        assert_eq!(trapcode::handle_trap(0xF025, &mut memory, &mut registers), TrapOutcome::Halt);
+    }
+
+    #[test]
+    fn test_vm_messages_are_notices() {
+        let (output, notices) = (BufferConsole::new(), BufferConsole::new());
+        let mut memory = Memory::new();
+        memory.set_console(Box::new(Notices { output: output.clone(), notices: notices.clone() }));
+        let mut registers = Registers::new();
+
+        registers.set(0, b'x' as u16);
+        trapcode::handle_trap(0xF021, &mut memory, &mut registers);
+        trapcode::handle_trap(0xF0FF, &mut memory, &mut registers);
+        assert_eq!(trapcode::handle_trap(0xF025, &mut memory, &mut registers), TrapOutcome::Halt);
+        assert_eq!(output.output(), b"x");
+        assert_eq!(notices.output(), b"TRAP 0xFF not implemented\n\nHALT\n");
+    }
+
+    #[test]
+    fn test_os_trap_jumps_through_vector_table() {
+        let (mut memory, mut registers, console) = setup(b"");
+        memory.write(0x0025, 0x0520);
+
+        trapcode::handle_os_trap(0xF025, &mut memory, &mut registers);
+        assert_eq!(registers.get_pc(), 0x0520);
+        assert_eq!(registers.get(7), 0x3001);
+        assert!(console.output().is_empty());
+    }
+}
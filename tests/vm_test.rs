@@ -0,0 +1,486 @@
+mod common;
+
+use common::TempFile;
+use lc3_vm::console::{BufferConsole, NullConsole};
+use lc3_vm::coverage::{BranchCount, Coverage};
+use lc3_vm::loader::LoadError;
+use lc3_vm::memory::{WatchHit, WatchKind, Watchpoint, MR_KBSR, MR_MCR, MR_PSR};
+use lc3_vm::opcode::OpCode;
+use lc3_vm::profile::Profiler;
+use lc3_vm::register::Privilege;
+use lc3_vm::trace::{TraceFormat, Tracer};
+use lc3_vm::{StopReason, Vm};
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(vm: &mut Vm, origin: u16, words: &[u16]) {
+        for (i, word) in words.iter().enumerate() {
+            vm.memory_mut().write(origin + i as u16, *word);
+        }
+    }
+
+    #[test]
+    fn test_run_until_halt() {
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[
+            0b0001000000100011, // ADD R0, R0, #3
+            0b0001000000000000, // ADD R0, R0, R0
+            0xF025,             // HALT
+        ]);
+
+        assert_eq!(vm.run(), StopReason::Halted);
+        assert_eq!(vm.registers().get(0), 6);
+    }
+
+    #[test]
+    fn test_step_and_run_for() {
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[
+            0b0001000000100001, // ADD R0, R0, #1
+            0b0000111111111110, // BRnzp #-2
+        ]);
+
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.registers().get_pc(), 0x3001);
+
+        // infinite loop: budget runs out without a stop reason
+        assert_eq!(vm.run_for(9), None);
+        assert_eq!(vm.registers().get(0), 5);
+    }
+
+    #[test]
+    fn test_breakpoint_stops_and_resumes() {
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[
+            0b0001000000100001, // ADD R0, R0, #1
+            0b0001000000100001, // ADD R0, R0, #1
+            0xF025,             // HALT
+        ]);
+        vm.add_breakpoint(0x3001);
+
+        assert_eq!(vm.run(), StopReason::Breakpoint(0x3001));
+        assert_eq!(vm.registers().get(0), 1);
+
+        assert_eq!(vm.run(), StopReason::Halted);
+        assert_eq!(vm.registers().get(0), 2);
+    }
+
+    #[test]
+    fn test_illegal_opcode() {
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[0xD000]); // reserved opcode
+        vm.set_stop_on_exception(true);
+
+        assert_eq!(vm.run(), StopReason::IllegalOpcode { pc: 0x3000, instr: 0xD000 });
+        assert_eq!(vm.registers().get_pc(), 0x3000);
+    }
+
+    #[test]
+    fn test_exception_without_handler_stops() {
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[0xD000, 0xF025]); // reserved opcode, HALT
+
+        assert_eq!(vm.run(), StopReason::IllegalOpcode { pc: 0x3000, instr: 0xD000 });
+        assert_eq!(vm.registers().get_pc(), 0x3000);
+        assert_eq!(vm.registers().get_privilege(), Privilege::User);
+
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[0x8000]); // RTI
+        assert_eq!(vm.run(), StopReason::PrivilegeViolation { pc: 0x3000, instr: 0x8000 });
+    }
+
+    #[test]
+    fn test_illegal_opcode_exception() {
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[0xD000]); // reserved opcode
+        load(&mut vm, 0x0101, &[0x1200]); // illegal opcode exception handler
+        load(&mut vm, 0x1200, &[0xF025]); // HALT
+
+        assert_eq!(vm.run(), StopReason::Halted);
+        assert_eq!(vm.registers().get(6), 0x2FFE);
+        assert_eq!(vm.memory().peek(0x2FFE), 0x3001);
+    }
+
+    #[test]
+    fn test_stop_on_privilege_violation() {
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[0x8000]); // RTI
+        vm.set_stop_on_exception(true);
+
+        assert_eq!(vm.step(), Some(StopReason::PrivilegeViolation { pc: 0x3000, instr: 0x8000 }));
+        assert_eq!(vm.registers().get_pc(), 0x3000);
+    }
+
+    #[test]
+    fn test_watchpoint_stops_after_access() {
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[
+            0b0001000000100111, // ADD R0, R0, #7
+            0b0011000000000010, // ST R0, #2
+            0xF025,             // HALT
+        ]);
+        vm.add_watchpoint(Watchpoint { start: 0x3004, end: 0x3004, kind: WatchKind::Write });
+
+        let hit = WatchHit { address: 0x3004, kind: WatchKind::Write, old: 0, new: 7 };
+        assert_eq!(vm.run(), StopReason::Watchpoint { pc: 0x3001, hit });
+        assert_eq!(vm.registers().get_pc(), 0x3002);
+        assert_eq!(vm.run(), StopReason::Halted);
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[
+            0b0001000000100111, // ADD R0, R0, #7
+            0b0011000000000010, // ST R0, #2
+            0xF025,             // HALT
+        ]);
+        let buffer = SharedBuffer::default();
+        vm.set_tracer(Tracer::new(buffer.clone(), TraceFormat::Json));
+
+        assert_eq!(vm.run(), StopReason::Halted);
+        vm.take_tracer().unwrap().finish().unwrap();
+        let trace = String::from_utf8(buffer.0.take()).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines, [
+            r#"{"pc":12288,"instr":4135,"asm":"ADD R0, R0, #7","regs":{"R0":7},"cc":"P","writes":[]}"#,
+            r#"{"pc":12289,"instr":12290,"asm":"ST R0, x3004","regs":{},"cc":"P","writes":[{"addr":12292,"old":0,"new":7}]}"#,
+            r#"{"pc":12290,"instr":61477,"asm":"HALT","regs":{"R7":12291},"cc":"P","writes":[]}"#,
+        ]);
+    }
+
+    #[test]
+    fn test_rti_returns_to_user_mode() {
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[0x8000]); // RTI
+        load(&mut vm, 0x2FFE, &[0x4000, 0x8001]); // saved PC and PSR
+        let regs = vm.registers_mut();
+        regs.set_psr(0x0002);
+        regs.set(6, 0x2FFE);
+        regs.set_saved_usp(0xF000);
+
+        assert_eq!(vm.step(), None);
+        let regs = vm.registers();
+        assert_eq!(regs.get_pc(), 0x4000);
+        assert_eq!(regs.get_privilege(), Privilege::User);
+        assert_eq!(regs.get_cond_flag(), 1);
+        assert_eq!(regs.get(6), 0xF000);
+        assert_eq!(regs.get_saved_ssp(), 0x3000);
+    }
+
+    #[test]
+    fn test_rti_in_user_mode_raises_exception() {
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[0x8000]); // RTI
+        load(&mut vm, 0x0100, &[0x1000]); // privilege mode exception handler
+        vm.registers_mut().set(6, 0xF000);
+
+        assert_eq!(vm.step(), None);
+        let regs = vm.registers();
+        assert_eq!(regs.get_pc(), 0x1000);
+        assert_eq!(regs.get_privilege(), Privilege::Supervisor);
+        assert_eq!(regs.get(6), 0x2FFE);
+        assert_eq!(regs.get_saved_usp(), 0xF000);
+        assert_eq!(vm.memory().peek(0x2FFE), 0x3001);
+        assert_eq!(vm.memory().peek(0x2FFF), 0x8004); // user mode, N from setting R6
+
+        // returning from the handler restores the user stack
+        load(&mut vm, 0x1000, &[0x8000]);
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.registers().get_pc(), 0x3001);
+        assert_eq!(vm.registers().get(6), 0xF000);
+    }
+
+    #[test]
+    fn test_memory_mapped_psr() {
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[
+            0b1010000000000001, // LDI R0, #1
+            0b1011001000000000, // STI R1, #0
+            0xFFFC,
+        ]);
+        vm.registers_mut().set(1, 0x0704);
+
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.registers().get(0), 0x8001);
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.registers().get_psr(), 0x0704);
+        assert_eq!(vm.registers().get_privilege(), Privilege::Supervisor);
+        assert_eq!(vm.memory().peek(MR_PSR), 0x0704);
+    }
+
+    #[test]
+    fn test_keyboard_interrupt() {
+        let mut vm = Vm::new(BufferConsole::with_input(b"a"));
+        load(&mut vm, 0x3000, &[
+            0b0001000000100001, // ADD R0, R0, #1
+            0b0000111111111110, // BRnzp #-2
+        ]);
+        load(&mut vm, 0x0180, &[0x1000]); // keyboard vector x80
+        load(&mut vm, 0x1000, &[
+            0b1010001000000001, // LDI R1, #1
+            0x8000,             // RTI
+            0xFE02,             // KBDR
+        ]);
+        vm.registers_mut().set(6, 0xF000);
+        vm.memory_mut().write(MR_KBSR, 0x4000);
+
+        assert_eq!(vm.step(), None);
+        let regs = vm.registers();
+        assert_eq!(regs.get_pc(), 0x1000);
+        assert_eq!(regs.get_psr() & 0x8700, 0x0400); // supervisor mode, priority 4
+        assert_eq!(vm.memory().peek(0x2FFE), 0x3001);
+
+        assert_eq!(vm.run_for(2), None);
+        assert_eq!(vm.registers().get(1), b'a' as u16);
+        assert_eq!(vm.registers().get_pc(), 0x3001);
+        assert_eq!(vm.registers().get_priority(), 0);
+        assert_eq!(vm.registers().get(6), 0xF000);
+    }
+
+    #[test]
+    fn test_interrupt_masked_by_priority() {
+        let mut vm = Vm::new(BufferConsole::with_input(b"a"));
+        load(&mut vm, 0x3000, &[0b0001000000100001]); // ADD R0, R0, #1
+        vm.registers_mut().set_psr(0x8402); // user mode, priority 4
+        vm.memory_mut().write(MR_KBSR, 0x4000);
+
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.registers().get_pc(), 0x3001);
+    }
+
+    #[test]
+    fn test_boot_os_image() {
+        let mut image = vec![0x00, 0x21, 0x04, 0x00]; // origin x0021, OUT routine at x0400
+        image.resize(2 + (0x0200 - 0x0021) * 2, 0);
+        image.extend([0xF0, 0x21]); // x0200: TRAP x21
+        let file = TempFile::new("os.obj");
+        let path = file.path();
+        std::fs::write(path, &image).unwrap();
+
+        let mut vm = Vm::new(NullConsole);
+        vm.load_os(path.to_str().unwrap()).unwrap();
+        assert_eq!(vm.registers().get_pc(), 0x0200);
+        assert_eq!(vm.registers().get_privilege(), Privilege::Supervisor);
+
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.registers().get_pc(), 0x0400);
+        assert_eq!(vm.registers().get(7), 0x0201);
+
+        std::fs::write(path, [0x2F, 0xFF, 0x00, 0x00, 0x00, 0x00]).unwrap();
+        assert!(vm.load_os(path.to_str().unwrap()).is_err()); // runs past x2FFF
+    }
+
+    #[test]
+    fn test_load_program_sets_entry_point() {
+        let file = TempFile::new("entry.obj");
+        let path = file.path();
+        std::fs::write(path, [0x40, 0x00, 0xF0, 0x25]).unwrap();
+
+        let mut vm = Vm::new(NullConsole);
+        assert_eq!(vm.load_program(path.to_str().unwrap()).unwrap(), 0x4000);
+        assert_eq!(vm.registers().get_pc(), 0x4000);
+        assert_eq!(vm.run(), StopReason::Halted);
+
+        std::fs::write(path, [0x40, 0x00, 0xF0]).unwrap();
+        assert!(matches!(vm.load_program(path.to_str().unwrap()), Err(LoadError::OddLength { len: 3, .. })));
+    }
+
+    #[test]
+    fn test_mcr_stops_the_clock() {
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[
+            0b1010000000000010, // LDI R0, #2
+            0b1011001000000001, // STI R1, #1
+            0b0001000000100001, // ADD R0, R0, #1
+            MR_MCR,
+        ]);
+        vm.registers_mut().set(1, 0x0000);
+
+        assert_eq!(vm.run(), StopReason::MachineHalted);
+        assert_eq!(vm.registers().get(0), 0x8000);
+        assert_eq!(vm.registers().get_pc(), 0x3002);
+        assert_eq!(vm.step(), Some(StopReason::MachineHalted));
+        assert_eq!(vm.registers().get_pc(), 0x3002);
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[
+            0b0001000000100001, // ADD R0, R0, #1
+            0b0011000000000010, // ST R0, #2
+            0b0000111111111101, // BRnzp #-3
+        ]);
+        vm.run_for(4);
+        vm.registers_mut().set_saved_ssp(0x2F00);
+        let snapshot = vm.snapshot();
+        assert_eq!(snapshot.pc, 0x3001);
+        assert_eq!(snapshot.psr, 0x8001);
+
+        vm.run_for(10);
+        vm.set_os_traps(true);
+        vm.registers_mut().set_privilege(Privilege::Supervisor);
+        vm.restore(&snapshot);
+        assert_eq!(vm.snapshot(), snapshot);
+        assert_eq!(vm.registers().get(0), 2);
+        assert_eq!(vm.memory().peek(0x3004), 1);
+        assert_eq!(vm.memory().peek(MR_PSR), 0x8001);
+        assert_eq!(vm.registers().get_privilege(), Privilege::User);
+        assert_eq!(vm.registers().get_saved_ssp(), 0x2F00);
+
+        vm.step();
+        assert_eq!(vm.memory().peek(0x3004), 2);
+    }
+
+    #[test]
+    fn test_step_back() {
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[
+            0b0001000000100101, // ADD R0, R0, #5
+            0b0011000000000010, // ST R0, #2
+            0b1010001000000010, // LDI R1, #2 (PSR)
+            0xF025,             // HALT
+            0x0000,
+            MR_PSR,
+        ]);
+        assert_eq!(vm.step_back(), None);
+        vm.set_history_limit(2);
+
+        assert_eq!(vm.run(), StopReason::Halted);
+        assert_eq!(vm.history().len(), 2);
+        assert_eq!(vm.step_back(), Some(0x3003));
+        assert_eq!(vm.registers().get(7), 0);
+        assert_eq!(vm.step_back(), Some(0x3002));
+        assert_eq!(vm.registers().get(1), 0);
+        assert_eq!(vm.registers().get_cond_flag(), 1); // P, from the ADD
+        assert_eq!(vm.step_back(), None); // older instructions were forgotten
+        assert_eq!(vm.memory().peek(0x3004), 5);
+
+        vm.set_history_limit(10);
+        vm.registers_mut().set_pc(0x3001);
+        vm.step();
+        assert_eq!(vm.history().last_write_to(0x3004).unwrap().1.old, 5);
+        vm.registers_mut().set(0, 9);
+        vm.step_back();
+        vm.step();
+        assert_eq!(vm.memory().peek(0x3004), 9);
+        vm.step_back();
+        assert_eq!(vm.memory().peek(0x3004), 5);
+        assert_eq!(vm.registers().get_pc(), 0x3001);
+    }
+
+    #[test]
+    fn test_step_back_over_interrupt() {
+        let mut vm = Vm::new(BufferConsole::with_input(b"k"));
+        load(&mut vm, 0x0180, &[0x1000]);
+        load(&mut vm, 0x3000, &[0b0001000000100001]); // ADD R0, R0, #1
+        vm.memory_mut().write(MR_KBSR, 0x4000);
+        vm.set_history_limit(10);
+
+        vm.step();
+        assert_eq!(vm.registers().get_pc(), 0x1000);
+        vm.step_back();
+        assert_eq!(vm.registers().get_pc(), 0x3000);
+        assert_eq!(vm.registers().get_privilege(), Privilege::User);
+        assert_eq!(vm.registers().get(6), 0);
+        assert_eq!(vm.memory().peek(0x2FFF), 0);
+        assert_eq!(vm.memory().peek(MR_PSR), 0x8002);
+    }
+
+    #[test]
+    fn test_profiler() {
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[
+            0b0010001000000101, // LD R1, #5
+            0b0100000001000000, // JSRR R1
+            0b0100000001000000, // JSRR R1
+            0xF025,             // HALT
+            0b0001000000100001, // x3004: ADD R0, R0, #1
+            0b1100000111000000, // RET
+            0x3004,
+        ]);
+        vm.set_profiler(Profiler::new());
+
+        assert_eq!(vm.run(), StopReason::Halted);
+        let profiler = vm.take_profiler().unwrap();
+        assert_eq!(profiler.total(), 8);
+        assert_eq!(profiler.count_at(0x3004), 2);
+        assert_eq!(profiler.opcode_count(OpCode::JSR), 2);
+        assert_eq!(profiler.subroutines()[&0x3004], (2, 4, 4));
+        assert_eq!(profiler.subroutines()[&0x3000], (0, 4, 8));
+    }
+
+    #[test]
+    fn test_coverage() {
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[
+            0b0001000000100010, // ADD R0, R0, #2
+            0b0001000000111111, // LOOP: ADD R0, R0, #-1
+            0b0000001111111110, // BRp LOOP
+            0xF025,             // HALT
+            0xD000,             // never reached
+        ]);
+        vm.set_coverage(Coverage::new());
+
+        assert_eq!(vm.run(), StopReason::Halted);
+        let coverage = vm.take_coverage().unwrap();
+        assert_eq!(coverage.hits(0x3001), 2);
+        assert_eq!(coverage.hits(0x3004), 0);
+        assert_eq!(coverage.branch(0x3002), Some(BranchCount { taken: 1, not_taken: 1 }));
+        assert_eq!(coverage.branch(0x3001), None);
+    }
+
+    #[test]
+    fn test_instruction_limit() {
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[
+            0b0001000000100001, // ADD R0, R0, #1
+            0b0000111111111110, // BRnzp #-2
+        ]);
+        vm.set_instruction_limit(Some(10));
+
+        assert_eq!(vm.run(), StopReason::InstructionLimit);
+        assert_eq!(vm.instructions_executed(), 10);
+        assert_eq!(vm.registers().get(0), 5);
+        assert_eq!(vm.run_for(5), Some(StopReason::InstructionLimit));
+
+        vm.set_instruction_limit(Some(12));
+        assert_eq!(vm.run_for(5), Some(StopReason::InstructionLimit));
+        assert_eq!(vm.registers().get(0), 6);
+        vm.set_instruction_limit(None);
+        assert_eq!(vm.run_for(5), None);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut vm = Vm::new(NullConsole);
+        load(&mut vm, 0x3000, &[0b0000111111111111]); // BRnzp #-1
+        vm.set_timeout(Some(Duration::from_millis(20)));
+
+        let start = Instant::now();
+        assert_eq!(vm.run(), StopReason::Timeout);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(vm.instructions_executed() > 0);
+    }
+}